@@ -16,6 +16,12 @@ fn main() {
                 .required(true)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .takes_value(true)
+                .help("Number of compositor threads (1 composites on the main thread)"),
+        )
         .get_matches();
 
     // Calling .unwrap() is safe here because "INPUT" is required (if "INPUT" wasn't
@@ -27,15 +33,21 @@ fn main() {
         process::exit(0);
     }).expect("Error setting Ctrl-C handler");
 
-    match run(matches.value_of("INPUT").unwrap()) {
+    let threads = matches
+        .value_of("threads")
+        .and_then(|threads| threads.parse::<usize>().ok())
+        .unwrap_or(1);
+
+    match run(matches.value_of("INPUT").unwrap(), threads) {
         Ok(_) => {}
         Err(e) => println!("{:?}", e),
     };
 }
 
-fn run(input: &str) -> Result<(), String> {
+fn run(input: &str, threads: usize) -> Result<(), String> {
     match fb2d::scene::Scene::new_from_bundle(input) {
         Ok(mut scene) => {
+            scene.set_compositor_threads(threads);
             #[cfg(not(target_os = "linux"))]
             let mut fb = fb2d::screen_writer_for_png("frame_buffer.png", 1920, 1080)?;
             #[cfg(target_os = "linux")]
@@ -0,0 +1,430 @@
+extern crate gif;
+extern crate image;
+extern crate png;
+
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::rc::Rc;
+use std::time;
+
+use image::{imageops, RgbaImage};
+
+use dimension::*;
+use image_filter::{apply_filters, filters_key, ImageFilter};
+use sprite::pixels_from_rgba;
+
+/// Browsers play frames with a zero or tiny delay at this rate, so do we.
+const DEFAULT_FRAME_DELAY_MS: u64 = 100;
+
+/// Fully composed animation frame, the size of the whole animation.
+pub struct AnimationFrame {
+    pub image: RgbaImage,
+    pub delay: time::Duration,
+}
+
+/// Decoded animated GIF or APNG with its playback state.
+pub struct Animation {
+    frames: Rc<Vec<AnimationFrame>>,
+    /// Number of plays, 0 plays forever.
+    pub loop_count: u32,
+    playing: bool,
+    current_frame: usize,
+    frame_started: Option<time::Duration>,
+    plays: u32,
+    scaled_frames: Vec<Vec<u32>>,
+    scaled_size: Size,
+    scaled_filter: Option<imageops::FilterType>,
+    scaled_filters_key: String,
+}
+
+impl Animation {
+    /// Animation of decoded `frames`, which may be shared with other animations.
+    pub fn new(frames: Rc<Vec<AnimationFrame>>, loop_count: u32) -> Animation {
+        Animation {
+            frames,
+            loop_count,
+            playing: true,
+            current_frame: 0,
+            frame_started: None,
+            plays: 0,
+            scaled_frames: Vec::new(),
+            scaled_size: Size { width: 0, height: 0 },
+            scaled_filter: None,
+            scaled_filters_key: String::new(),
+        }
+    }
+
+    pub fn load_gif(filename: &str) -> Result<Animation, String> {
+        use self::gif::SetParameter;
+
+        let data = fs::read(filename).map_err(|e| format!("{} {}", e, filename))?;
+        let mut decoder = gif::Decoder::new(io::Cursor::new(&data));
+        decoder.set(gif::ColorOutput::RGBA);
+        let mut reader = decoder.read_info().map_err(|e| format!("{} {}", e, filename))?;
+
+        let mut canvas = RgbaImage::new(reader.width() as u32, reader.height() as u32);
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.read_next_frame().map_err(|e| format!("{} {}", e, filename))? {
+            let previous = if frame.dispose == gif::DisposalMethod::Previous {
+                Some(canvas.clone())
+            } else {
+                None
+            };
+            let frame_rect = Rect {
+                pos: Pos { x: frame.left as i32, y: frame.top as i32 },
+                size: Size { width: frame.width as i32, height: frame.height as i32 },
+            };
+            // Transparent GIF pixels leave the canvas untouched.
+            draw_frame(&mut canvas, &frame.buffer, &frame_rect, |dst, src| if src[3] != 0 { *dst = src });
+
+            frames.push(AnimationFrame {
+                image: canvas.clone(),
+                delay: frame_delay(frame.delay as u64 * 10),
+            });
+
+            match frame.dispose {
+                gif::DisposalMethod::Background => clear_rect(&mut canvas, &frame_rect),
+                gif::DisposalMethod::Previous => {
+                    if let Some(previous) = previous {
+                        canvas = previous;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if frames.is_empty() {
+            return Err(format!("No frames in {}", filename));
+        }
+        Ok(Animation::new(Rc::new(frames), gif_loop_count(&data)))
+    }
+
+    /// Returns None for a PNG without animation control, which is loaded as a still image.
+    pub fn load_apng(filename: &str) -> Result<Option<Animation>, String> {
+        let file = fs::File::open(filename).map_err(|e| format!("{} {}", e, filename))?;
+        let mut decoder = png::Decoder::new(io::BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8() | png::Transformations::ALPHA);
+        let mut reader = decoder.read_info().map_err(|e| format!("{} {}", e, filename))?;
+
+        let animation_control = match reader.info().animation_control {
+            Some(animation_control) => animation_control,
+            None => return Ok(None),
+        };
+        let (width, height) = reader.info().size();
+        // Without a frame control before the image data the default image is not part of the animation.
+        let skip_default_image = reader.info().frame_control.is_none();
+        let frame_count = animation_control.num_frames as usize + if skip_default_image { 1 } else { 0 };
+
+        let mut canvas = RgbaImage::new(width, height);
+        let mut frames = Vec::new();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        for index in 0..frame_count {
+            let output_info = reader.next_frame(&mut buffer).map_err(|e| format!("{} {}", e, filename))?;
+            if index == 0 && skip_default_image {
+                continue;
+            }
+            let frame_control = match reader.info().frame_control {
+                Some(frame_control) => frame_control,
+                None => break,
+            };
+            let data = &buffer[..output_info.buffer_size()];
+            let rgba: Vec<u8> = match output_info.color_type {
+                png::ColorType::Rgba => data.to_vec(),
+                png::ColorType::GrayscaleAlpha => data.chunks(2).flat_map(|ga| vec![ga[0], ga[0], ga[0], ga[1]]).collect(),
+                color_type => return Err(format!("Unsupported color type {:?} in {}", color_type, filename)),
+            };
+
+            let previous = if frame_control.dispose_op == png::DisposeOp::Previous {
+                Some(canvas.clone())
+            } else {
+                None
+            };
+            let frame_rect = Rect {
+                pos: Pos { x: frame_control.x_offset as i32, y: frame_control.y_offset as i32 },
+                size: Size { width: frame_control.width as i32, height: frame_control.height as i32 },
+            };
+            match frame_control.blend_op {
+                png::BlendOp::Source => draw_frame(&mut canvas, &rgba, &frame_rect, |dst, src| *dst = src),
+                png::BlendOp::Over => draw_frame(&mut canvas, &rgba, &frame_rect, blend_over),
+            }
+
+            let delay_den = if frame_control.delay_den == 0 { 100 } else { frame_control.delay_den as u64 };
+            frames.push(AnimationFrame {
+                image: canvas.clone(),
+                delay: frame_delay(frame_control.delay_num as u64 * 1000 / delay_den),
+            });
+
+            match frame_control.dispose_op {
+                png::DisposeOp::Background => clear_rect(&mut canvas, &frame_rect),
+                // Restoring before the first frame means clearing it.
+                png::DisposeOp::Previous if frames.len() == 1 => clear_rect(&mut canvas, &frame_rect),
+                png::DisposeOp::Previous => {
+                    if let Some(previous) = previous {
+                        canvas = previous;
+                    }
+                }
+                png::DisposeOp::None => {}
+            }
+        }
+        if frames.is_empty() {
+            return Err(format!("No frames in {}", filename));
+        }
+        Ok(Some(Animation::new(Rc::new(frames), animation_control.num_plays)))
+    }
+
+    /// Decoded frames, shared by the animations made from them.
+    pub fn frames(&self) -> Rc<Vec<AnimationFrame>> {
+        self.frames.clone()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.frames[0].image.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frames[0].image.height()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Continues from the current frame, a finished animation starts over.
+    pub fn play(&mut self) {
+        if !self.playing {
+            if self.loop_count != 0 && self.plays >= self.loop_count {
+                self.plays = 0;
+                self.current_frame = 0;
+            }
+            self.playing = true;
+            self.frame_started = None;
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Advances frames by their delays. Returns true when the current frame changed.
+    pub fn update(&mut self, clock: time::Duration) -> bool {
+        if !self.playing || self.frames.len() < 2 {
+            return false;
+        }
+        let mut frame_started = *self.frame_started.get_or_insert(clock);
+        let mut changed = false;
+        while clock >= frame_started + self.frames[self.current_frame].delay {
+            frame_started += self.frames[self.current_frame].delay;
+            if self.current_frame + 1 < self.frames.len() {
+                self.current_frame += 1;
+            } else {
+                self.plays += 1;
+                if self.loop_count != 0 && self.plays >= self.loop_count {
+                    self.playing = false;
+                    break;
+                }
+                self.current_frame = 0;
+            }
+            changed = true;
+        }
+        self.frame_started = Some(frame_started);
+        changed
+    }
+
+    /// Scales all frames and applies `filters` once; later calls with the same arguments are free.
+    pub fn scale_to(&mut self, width: i32, height: i32, filter: imageops::FilterType, filters: &[ImageFilter]) {
+        let size = Size { width: width.max(1), height: height.max(1) };
+        let key = filters_key(filters);
+        if self.scaled_size.width == size.width && self.scaled_size.height == size.height
+            && self.scaled_filter.map(|scaled_filter| scaled_filter as u8) == Some(filter as u8)
+            && self.scaled_filters_key == key
+        {
+            return;
+        }
+        self.scaled_frames = self
+            .frames
+            .iter()
+            .map(|frame| {
+                let image = imageops::resize(&frame.image, size.width as u32, size.height as u32, filter);
+                let mut pixels = pixels_from_rgba(&image.into_raw());
+                apply_filters(&mut pixels, &size, filters);
+                pixels
+            })
+            .collect();
+        self.scaled_size = size;
+        self.scaled_filter = Some(filter);
+        self.scaled_filters_key = key;
+    }
+
+    pub fn scaled_pixels(&self) -> &[u32] {
+        match self.scaled_frames.get(self.current_frame) {
+            Some(pixels) => pixels,
+            None => &[],
+        }
+    }
+}
+
+/// Whether the PNG at `filename` is animated, from an `acTL` chunk before the image data.
+/// Only chunk headers are read.
+pub fn is_apng(filename: &str) -> bool {
+    let mut file = match fs::File::open(filename) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    let mut signature = [0u8; 8];
+    if file.read_exact(&mut signature).is_err() || signature != *b"\x89PNG\r\n\x1a\n" {
+        return false;
+    }
+    let mut header = [0u8; 8];
+    while file.read_exact(&mut header).is_ok() {
+        match &header[4..] {
+            b"acTL" => return true,
+            b"IDAT" | b"IEND" => return false,
+            _ => {}
+        }
+        // Skips the chunk data and CRC.
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        if file.seek(SeekFrom::Current(length as i64 + 4)).is_err() {
+            return false;
+        }
+    }
+    false
+}
+
+/// Number of plays from the NETSCAPE2.0 application extension of the GIF `data`, which holds
+/// the repeats after the first play with 0 repeating forever. GIFs without it play once, as in
+/// browsers.
+fn gif_loop_count(data: &[u8]) -> u32 {
+    match gif_repeats(data) {
+        Some(0) => 0,
+        Some(repeats) => repeats as u32 + 1,
+        None => 1,
+    }
+}
+
+/// Walks the blocks of the GIF `data` up to its NETSCAPE2.0 extension.
+fn gif_repeats(data: &[u8]) -> Option<u16> {
+    let color_table_size = |packed: u8| if packed & 0x80 != 0 { 3 << ((packed & 0x07) + 1) } else { 0 };
+    // Header and logical screen descriptor.
+    let mut offset = 13 + color_table_size(*data.get(10)?);
+    loop {
+        match *data.get(offset)? {
+            0x21 => {
+                let label = *data.get(offset + 1)?;
+                let (sub_blocks, end) = gif_sub_blocks(data, offset + 2)?;
+                if label == 0xFF && sub_blocks.len() >= 2 && sub_blocks[0] == b"NETSCAPE2.0" {
+                    if let [1, low, high] = *sub_blocks[1] {
+                        return Some(u16::from_le_bytes([low, high]));
+                    }
+                }
+                offset = end;
+            }
+            0x2C => {
+                // Image descriptor, local color table and LZW code size come before the data.
+                let packed = *data.get(offset + 9)?;
+                let (_, end) = gif_sub_blocks(data, offset + 10 + color_table_size(packed) + 1)?;
+                offset = end;
+            }
+            // Trailer, or data that is no GIF block.
+            _ => return None,
+        }
+    }
+}
+
+/// Sub-blocks from `offset` to the terminating empty one, with the offset after it.
+fn gif_sub_blocks(data: &[u8], mut offset: usize) -> Option<(Vec<&[u8]>, usize)> {
+    let mut sub_blocks = Vec::new();
+    loop {
+        let length = *data.get(offset)? as usize;
+        offset += 1;
+        if length == 0 {
+            return Some((sub_blocks, offset));
+        }
+        sub_blocks.push(data.get(offset..offset + length)?);
+        offset += length;
+    }
+}
+
+fn frame_delay(delay_ms: u64) -> time::Duration {
+    if delay_ms <= 10 {
+        time::Duration::from_millis(DEFAULT_FRAME_DELAY_MS)
+    } else {
+        time::Duration::from_millis(delay_ms)
+    }
+}
+
+fn draw_frame<F>(canvas: &mut RgbaImage, rgba: &[u8], frame_rect: &Rect, mut put: F)
+where
+    F: FnMut(&mut image::Rgba<u8>, image::Rgba<u8>),
+{
+    for (index, src) in rgba.chunks(4).enumerate() {
+        let x = frame_rect.pos.x as u32 + index as u32 % frame_rect.size.width as u32;
+        let y = frame_rect.pos.y as u32 + index as u32 / frame_rect.size.width as u32;
+        if x < canvas.width() && y < canvas.height() {
+            put(canvas.get_pixel_mut(x, y), image::Rgba { data: [src[0], src[1], src[2], src[3]] });
+        }
+    }
+}
+
+fn blend_over(dst: &mut image::Rgba<u8>, src: image::Rgba<u8>) {
+    let src_alpha = src[3] as u32;
+    if src_alpha == 255 {
+        *dst = src;
+        return;
+    }
+    let dst_alpha = dst[3] as u32 * (255 - src_alpha) / 255;
+    let alpha = src_alpha + dst_alpha;
+    if alpha == 0 {
+        *dst = image::Rgba { data: [0, 0, 0, 0] };
+        return;
+    }
+    for channel in 0..3 {
+        dst[channel] = ((src[channel] as u32 * src_alpha + dst[channel] as u32 * dst_alpha) / alpha) as u8;
+    }
+    dst[3] = alpha as u8;
+}
+
+fn clear_rect(canvas: &mut RgbaImage, rect: &Rect) {
+    for y in rect.pos.y..rect.pos.y + rect.size.height {
+        for x in rect.pos.x..rect.pos.x + rect.size.width {
+            if (x as u32) < canvas.width() && (y as u32) < canvas.height() {
+                canvas.put_pixel(x as u32, y as u32, image::Rgba { data: [0, 0, 0, 0] });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gif::SetParameter;
+
+    fn encode_gif(repeat: Option<gif::Repeat>, comment: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut data, 2, 2, &[0, 0, 0, 0xFF, 0xFF, 0xFF]).unwrap();
+            if let Some(repeat) = repeat {
+                encoder.set(repeat).unwrap();
+            }
+            if !comment.is_empty() {
+                encoder.write_raw_extension(0xFE, &[comment]).unwrap();
+            }
+            let frame = gif::Frame::from_rgb(2, 2, &[0, 0, 0, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0xFF, 0xFF, 0xFF]);
+            encoder.write_frame(&frame).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn gif_loop_count_reads_netscape_extension() {
+        assert_eq!(gif_loop_count(&encode_gif(Some(gif::Repeat::Infinite), b"")), 0);
+        assert_eq!(gif_loop_count(&encode_gif(Some(gif::Repeat::Finite(3)), b"")), 4);
+        assert_eq!(gif_loop_count(&encode_gif(None, b"")), 1);
+    }
+
+    #[test]
+    fn gif_loop_count_ignores_extension_bytes_in_other_blocks() {
+        let comment = b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x05\x00";
+        assert_eq!(gif_loop_count(&encode_gif(None, comment)), 1);
+    }
+}
@@ -0,0 +1,426 @@
+extern crate qrcode;
+
+use std::time;
+
+use canvas::Canvas;
+use color::{self, Color};
+use dimension::*;
+use screen_writer::ScreenInfo;
+use sprite::Sprite;
+
+/// Quiet zone of QR codes in modules, as required by the specification.
+pub const QR_CODE_QUIET_ZONE: u32 = 4;
+
+/// Quiet zone left and right of linear barcodes in modules.
+pub const BARCODE_QUIET_ZONE: u32 = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorCorrection {
+    /// Recovers about 7% of the code.
+    Low,
+    /// Recovers about 15% of the code.
+    Medium,
+    /// Recovers about 25% of the code.
+    Quartile,
+    /// Recovers about 30% of the code.
+    High,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Symbology {
+    /// Printable ASCII, digit runs are packed in pairs.
+    Code128,
+    /// 12 digits and a check digit, which is computed when only 12 are given.
+    Ean13,
+}
+
+/// Dark and light modules of an encoded symbol, row by row.
+struct Modules {
+    columns: u32,
+    rows: u32,
+    dark: Vec<bool>,
+}
+
+const NO_MODULES: Modules = Modules {
+    columns: 0,
+    rows: 0,
+    dark: Vec::new(),
+};
+
+/// QR code scaled by a whole number of pixels per module, so modules stay sharp, and placed
+/// inside the node frame by `gravity`.
+pub struct QrCodeSprite {
+    pub color: Color,
+    pub background: Color,
+    pub gravity: Gravity,
+    /// Light border around the code in modules.
+    pub quiet_zone: u32,
+    data: String,
+    error_correction: ErrorCorrection,
+    modules: Modules,
+    changed: bool,
+    canvas: Canvas,
+}
+
+impl QrCodeSprite {
+    pub fn new() -> QrCodeSprite {
+        QrCodeSprite {
+            color: color::BLACK,
+            background: color::WHITE,
+            gravity: GRAVITY_CENTER,
+            quiet_zone: QR_CODE_QUIET_ZONE,
+            data: String::new(),
+            error_correction: ErrorCorrection::Medium,
+            modules: NO_MODULES,
+            changed: false,
+            canvas: Canvas::new(0, 0),
+        }
+    }
+
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    /// Encodes `data` and redraws the node on the next scene update. Nothing is drawn when
+    /// the data does not fit in a QR code.
+    pub fn set_data(&mut self, data: &str) -> Result<(), String> {
+        self.data = data.to_string();
+        self.encode()
+    }
+
+    pub fn error_correction(&self) -> ErrorCorrection {
+        self.error_correction
+    }
+
+    pub fn set_error_correction(&mut self, error_correction: ErrorCorrection) -> Result<(), String> {
+        self.error_correction = error_correction;
+        self.encode()
+    }
+
+    fn encode(&mut self) -> Result<(), String> {
+        self.changed = true;
+        self.modules = NO_MODULES;
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        let ec_level = match self.error_correction {
+            ErrorCorrection::Low => qrcode::EcLevel::L,
+            ErrorCorrection::Medium => qrcode::EcLevel::M,
+            ErrorCorrection::Quartile => qrcode::EcLevel::Q,
+            ErrorCorrection::High => qrcode::EcLevel::H,
+        };
+        match qrcode::QrCode::with_error_correction_level(self.data.as_bytes(), ec_level) {
+            Ok(code) => {
+                let width = code.width() as u32;
+                self.modules = Modules {
+                    columns: width,
+                    rows: width,
+                    dark: code.into_colors().into_iter().map(|module| module == qrcode::Color::Dark).collect(),
+                };
+                Ok(())
+            }
+            Err(e) => Err(format!("{} {}", e, self.data)),
+        }
+    }
+}
+
+impl Default for QrCodeSprite {
+    fn default() -> QrCodeSprite {
+        QrCodeSprite::new()
+    }
+}
+
+impl<'a> Sprite<'a> for QrCodeSprite {
+    fn draw(&mut self, outer_rect: &Rect, _screen_info: &ScreenInfo) {
+        self.changed = false;
+        draw_modules(
+            &mut self.canvas,
+            &self.modules,
+            self.quiet_zone,
+            false,
+            &outer_rect.size,
+            &self.gravity,
+            self.color,
+            self.background,
+        );
+    }
+
+    fn pixels(&self) -> (&[u32], &Rect) {
+        (self.canvas.pixels(), self.canvas.frame())
+    }
+
+    fn update(&mut self, _clock: time::Duration) -> bool {
+        self.changed
+    }
+}
+
+/// Linear barcode with bars as tall as the node, each module a whole number of pixels wide.
+/// Only the bars are drawn, without the human readable digits.
+pub struct BarcodeSprite {
+    pub color: Color,
+    pub background: Color,
+    pub gravity: Gravity,
+    /// Light margin left and right of the bars in modules.
+    pub quiet_zone: u32,
+    symbology: Symbology,
+    data: String,
+    modules: Modules,
+    changed: bool,
+    canvas: Canvas,
+}
+
+impl BarcodeSprite {
+    pub fn new(symbology: Symbology) -> BarcodeSprite {
+        BarcodeSprite {
+            color: color::BLACK,
+            background: color::WHITE,
+            gravity: GRAVITY_CENTER,
+            quiet_zone: BARCODE_QUIET_ZONE,
+            symbology,
+            data: String::new(),
+            modules: NO_MODULES,
+            changed: false,
+            canvas: Canvas::new(0, 0),
+        }
+    }
+
+    pub fn symbology(&self) -> Symbology {
+        self.symbology
+    }
+
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    /// Encodes `data` and redraws the node on the next scene update. Nothing is drawn when
+    /// the data can not be encoded in the symbology.
+    pub fn set_data(&mut self, data: &str) -> Result<(), String> {
+        self.data = data.to_string();
+        self.changed = true;
+        self.modules = NO_MODULES;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let bars = match self.symbology {
+            Symbology::Code128 => encode_code128(data)?,
+            Symbology::Ean13 => encode_ean13(data)?,
+        };
+        self.modules = Modules {
+            columns: bars.len() as u32,
+            rows: 1,
+            dark: bars,
+        };
+        Ok(())
+    }
+}
+
+impl<'a> Sprite<'a> for BarcodeSprite {
+    fn draw(&mut self, outer_rect: &Rect, _screen_info: &ScreenInfo) {
+        self.changed = false;
+        draw_modules(
+            &mut self.canvas,
+            &self.modules,
+            self.quiet_zone,
+            true,
+            &outer_rect.size,
+            &self.gravity,
+            self.color,
+            self.background,
+        );
+    }
+
+    fn pixels(&self) -> (&[u32], &Rect) {
+        (self.canvas.pixels(), self.canvas.frame())
+    }
+
+    fn update(&mut self, _clock: time::Duration) -> bool {
+        self.changed
+    }
+}
+
+/// Draws the largest whole module size fitting `outer_size`, at least one pixel. Linear codes
+/// `stretch` their single row to the full height and have no quiet zone above and below.
+#[allow(clippy::too_many_arguments)]
+fn draw_modules(
+    canvas: &mut Canvas,
+    modules: &Modules,
+    quiet_zone: u32,
+    stretch: bool,
+    outer_size: &Size,
+    gravity: &Gravity,
+    color: Color,
+    background: Color,
+) {
+    if modules.dark.is_empty() || outer_size.width <= 0 || outer_size.height <= 0 {
+        canvas.resize(0, 0);
+        return;
+    }
+    let columns = (modules.columns + 2 * quiet_zone) as i32;
+    let rows = if stretch { 1 } else { (modules.rows + 2 * quiet_zone) as i32 };
+    let module_width = if stretch {
+        (outer_size.width / columns).max(1)
+    } else {
+        (outer_size.width / columns).min(outer_size.height / rows).max(1)
+    };
+    let module_height = if stretch { outer_size.height } else { module_width };
+    canvas.resize(columns * module_width, rows * module_height);
+    canvas.set_position(Pos {
+        x: ((outer_size.width - canvas.width()) as f32 * gravity.x) as i32,
+        y: ((outer_size.height - canvas.height()) as f32 * gravity.y) as i32,
+    });
+    canvas.clear(background);
+    let border_rows = if stretch { 0 } else { quiet_zone as i32 };
+    for row in 0..modules.rows as i32 {
+        for column in 0..modules.columns as i32 {
+            if modules.dark[(row * modules.columns as i32 + column) as usize] {
+                let module = Rect {
+                    pos: Pos {
+                        x: (column + quiet_zone as i32) * module_width,
+                        y: (row + border_rows) * module_height,
+                    },
+                    size: Size {
+                        width: module_width,
+                        height: module_height,
+                    },
+                };
+                canvas.fill_rect(&module, color);
+            }
+        }
+    }
+}
+
+/// Bar and space widths of the Code 128 symbols by value, 103...105 are the start codes.
+const CODE128_PATTERNS: [&str; 106] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232",
+];
+
+const CODE128_STOP: &str = "2331112";
+const CODE128_CODE_B: u32 = 100;
+const CODE128_CODE_C: u32 = 99;
+const CODE128_START_B: u32 = 104;
+const CODE128_START_C: u32 = 105;
+
+/// Code 128 in code sets B and C, switching to C for runs of digits long enough to save space.
+fn encode_code128(data: &str) -> Result<Vec<bool>, String> {
+    let bytes = data.as_bytes();
+    if let Some(c) = data.chars().find(|c| !(' '..='\u{7F}').contains(c)) {
+        return Err(format!("Code 128 can not encode {:?} in {}", c, data));
+    }
+    let digits_at = |index: usize| bytes[index..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let mut values = Vec::new();
+    let mut code_c = false;
+    let mut index = 0;
+    while index < bytes.len() {
+        let digits = digits_at(index);
+        // Set C pays off for 4 digits at either end of the data and 6 in the middle.
+        let worth_c = digits >= 4 && (index == 0 || index + digits == bytes.len() || digits >= 6);
+        if code_c && digits < 2 {
+            values.push(CODE128_CODE_B);
+            code_c = false;
+        } else if !code_c && worth_c {
+            if digits % 2 == 1 {
+                // The odd digit goes in set B, leaving pairs for set C.
+                if index == 0 {
+                    values.push(CODE128_START_B);
+                }
+                values.push((bytes[index] - b' ') as u32);
+                index += 1;
+            }
+            values.push(if values.is_empty() { CODE128_START_C } else { CODE128_CODE_C });
+            code_c = true;
+        } else if values.is_empty() {
+            values.push(CODE128_START_B);
+        }
+        if code_c {
+            values.push(((bytes[index] - b'0') * 10 + bytes[index + 1] - b'0') as u32);
+            index += 2;
+        } else {
+            values.push((bytes[index] - b' ') as u32);
+            index += 1;
+        }
+    }
+    let checksum = values
+        .iter()
+        .enumerate()
+        .fold(0, |sum, (position, value)| sum + value * (position.max(1) as u32))
+        % 103;
+    values.push(checksum);
+
+    let mut bars = Vec::new();
+    for &value in &values {
+        push_widths(&mut bars, CODE128_PATTERNS[value as usize]);
+    }
+    push_widths(&mut bars, CODE128_STOP);
+    Ok(bars)
+}
+
+/// Appends alternating bars and spaces, starting with a bar, of the given widths.
+fn push_widths(bars: &mut Vec<bool>, widths: &str) {
+    for (index, width) in widths.bytes().enumerate() {
+        for _ in 0..width - b'0' {
+            bars.push(index % 2 == 0);
+        }
+    }
+}
+
+/// Left hand odd parity (L) digit patterns, right hand patterns are their inverse and even
+/// parity (G) ones the inverse reversed.
+const EAN_L_PATTERNS: [&str; 10] = [
+    "0001101", "0011001", "0010011", "0111101", "0100011", "0110001", "0101111", "0111011", "0110111", "0001011",
+];
+
+/// Parity of the six left hand digits, selected by the first digit.
+const EAN_PARITIES: [&str; 10] = [
+    "LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG", "LGGLLG", "LGGGLL", "LGLGGL", "LGLGLG", "LGGLGL",
+];
+
+fn encode_ean13(data: &str) -> Result<Vec<bool>, String> {
+    let mut digits: Vec<u8> = data.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !(digits.len() == 12 || digits.len() == 13) || digits.iter().any(|b| !b.is_ascii_digit()) {
+        return Err(format!("EAN-13 needs 12 or 13 digits {}", data));
+    }
+    for digit in digits.iter_mut() {
+        *digit -= b'0';
+    }
+    let weighted: u32 = digits
+        .iter()
+        .take(12)
+        .enumerate()
+        .map(|(index, &digit)| digit as u32 * if index % 2 == 0 { 1 } else { 3 })
+        .sum();
+    let check_digit = ((10 - weighted % 10) % 10) as u8;
+    if digits.len() == 13 && digits[12] != check_digit {
+        return Err(format!("EAN-13 check digit should be {} {}", check_digit, data));
+    }
+    digits.truncate(12);
+    digits.push(check_digit);
+
+    let pattern = |digit: u8, parity: u8| -> Vec<bool> {
+        let left: Vec<bool> = EAN_L_PATTERNS[digit as usize].bytes().map(|b| b == b'1').collect();
+        match parity {
+            b'L' => left,
+            b'R' => left.iter().map(|&dark| !dark).collect(),
+            _ => left.iter().rev().map(|&dark| !dark).collect(),
+        }
+    };
+    let mut bars = vec![true, false, true];
+    for (&digit, parity) in digits[1..7].iter().zip(EAN_PARITIES[digits[0] as usize].bytes()) {
+        bars.extend(pattern(digit, parity));
+    }
+    bars.extend(&[false, true, false, true, false]);
+    for &digit in &digits[7..] {
+        bars.extend(pattern(digit, b'R'));
+    }
+    bars.extend(&[true, false, true]);
+    Ok(bars)
+}
@@ -0,0 +1,157 @@
+use color::Color;
+use dimension::*;
+
+/// Pixel buffer a sprite draws into and hands to the compositor from `Sprite::pixels`.
+/// Pixels are straight alpha RGBA in the layout of `Color`, drawing outside of the canvas is
+/// ignored.
+pub struct Canvas {
+    pixels: Vec<u32>,
+    frame: Rect,
+}
+
+impl Canvas {
+    pub fn new(width: i32, height: i32) -> Canvas {
+        let mut canvas = Canvas {
+            pixels: Vec::new(),
+            frame: RECT_ZERO,
+        };
+        canvas.resize(width, height);
+        canvas
+    }
+
+    /// Resizes the canvas and clears it to transparent.
+    pub fn resize(&mut self, width: i32, height: i32) {
+        let width = width.max(0);
+        let height = height.max(0);
+        self.frame = Rect {
+            pos: POS_ZERO,
+            size: Size { width, height },
+        };
+        self.pixels.clear();
+        self.pixels.resize((width * height) as usize, 0);
+    }
+
+    pub fn width(&self) -> i32 {
+        self.frame.size.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.frame.size.height
+    }
+
+    /// Canvas area relative to the node frame, as returned by `Sprite::pixels`.
+    pub fn frame(&self) -> &Rect {
+        &self.frame
+    }
+
+    /// Places the canvas inside the node frame, e.g. to center it.
+    pub fn set_position(&mut self, pos: Pos) {
+        self.frame.pos = pos;
+    }
+
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [u32] {
+        &mut self.pixels
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        let Color(pixel) = color;
+        for dst in self.pixels.iter_mut() {
+            *dst = pixel;
+        }
+    }
+
+    pub fn pixel(&self, x: i32, y: i32) -> Option<Color> {
+        self.offset(x, y).map(|offset| Color(self.pixels[offset]))
+    }
+
+    /// Replaces the pixel, including its alpha.
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if let Some(offset) = self.offset(x, y) {
+            let Color(pixel) = color;
+            self.pixels[offset] = pixel;
+        }
+    }
+
+    /// Draws the color over the pixel.
+    pub fn blend_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if let Some(offset) = self.offset(x, y) {
+            let Color(pixel) = color;
+            self.pixels[offset] = blend_over(self.pixels[offset], pixel);
+        }
+    }
+
+    pub fn fill_rect(&mut self, rect: &Rect, color: Color) {
+        let Color(pixel) = color;
+        self.for_each_in_rect(rect, |dst| *dst = blend_over(*dst, pixel));
+    }
+
+    /// Draws `pixels` of `size` over the canvas with their top left corner at `pos`.
+    pub fn draw_pixels(&mut self, pos: &Pos, pixels: &[u32], size: &Size) {
+        let rect = Rect { pos: *pos, size: *size };
+        let canvas_width = self.width();
+        let clipped = self.clip(&rect);
+        for y in clipped.pos.y..clipped.pos.y + clipped.size.height {
+            for x in clipped.pos.x..clipped.pos.x + clipped.size.width {
+                let src = pixels[((y - pos.y) * size.width + x - pos.x) as usize];
+                let offset = (y * canvas_width + x) as usize;
+                self.pixels[offset] = blend_over(self.pixels[offset], src);
+            }
+        }
+    }
+
+    fn offset(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && x < self.width() && y < self.height() {
+            Some((y * self.width() + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn clip(&self, rect: &Rect) -> Rect {
+        let left = rect.pos.x.max(0);
+        let top = rect.pos.y.max(0);
+        let right = (rect.pos.x + rect.size.width).min(self.width());
+        let bottom = (rect.pos.y + rect.size.height).min(self.height());
+        Rect {
+            pos: Pos { x: left, y: top },
+            size: Size {
+                width: (right - left).max(0),
+                height: (bottom - top).max(0),
+            },
+        }
+    }
+
+    fn for_each_in_rect<F: FnMut(&mut u32)>(&mut self, rect: &Rect, mut f: F) {
+        let clipped = self.clip(rect);
+        let canvas_width = self.width();
+        for y in clipped.pos.y..clipped.pos.y + clipped.size.height {
+            let row_start = (y * canvas_width + clipped.pos.x) as usize;
+            for dst in &mut self.pixels[row_start..row_start + clipped.size.width as usize] {
+                f(dst);
+            }
+        }
+    }
+}
+
+/// Source over blending of straight alpha pixels.
+pub fn blend_over(dst: u32, src: u32) -> u32 {
+    let src_a = src >> 24;
+    if src_a == 0xFF {
+        return src;
+    }
+    if src_a == 0 {
+        return dst;
+    }
+    let dst_a = (dst >> 24) * (0xFF - src_a) / 0xFF;
+    let out_a = src_a + dst_a;
+    let channel = |shift: u32| {
+        let src_c = (src >> shift) & 0xFF;
+        let dst_c = (dst >> shift) & 0xFF;
+        ((src_c * src_a + dst_c * dst_a) / out_a) << shift
+    };
+    channel(0) | channel(8) | channel(16) | (out_a << 24)
+}
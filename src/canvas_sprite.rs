@@ -0,0 +1,309 @@
+extern crate image;
+extern crate rusttype;
+extern crate tiny_skia;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time;
+
+use image::RgbaImage;
+use rusttype::{point, Font, FontCollection, PositionedGlyph};
+
+use color::Color;
+use dimension::*;
+use screen_writer::ScreenInfo;
+use shape::{paint_for_color, skia_stroke, Stroke};
+use sprite::{pixels_from_pixmap, Sprite};
+use text::get_default_font_data;
+
+/// Immediate mode drawing into a `CanvasSprite`. Coordinates are in pixels of the node frame,
+/// moved by `translate`. `save` and `restore` keep the translation and the clip.
+pub struct Painter<'p> {
+    pixmap: &'p mut tiny_skia::Pixmap,
+    font: &'p Font<'static>,
+    transform: tiny_skia::Transform,
+    clip: Option<tiny_skia::Mask>,
+    saved: Vec<(tiny_skia::Transform, Option<tiny_skia::Mask>)>,
+}
+
+impl<'p> Painter<'p> {
+    pub(crate) fn new(pixmap: &'p mut tiny_skia::Pixmap, font: &'p Font<'static>) -> Painter<'p> {
+        Painter {
+            pixmap,
+            font,
+            transform: tiny_skia::Transform::identity(),
+            clip: None,
+            saved: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.pixmap.width() as f32
+    }
+
+    pub fn height(&self) -> f32 {
+        self.pixmap.height() as f32
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        let (r, g, b, a) = color.rgba();
+        self.pixmap.fill(tiny_skia::Color::from_rgba8(r, g, b, a));
+    }
+
+    pub fn fill_rect(&mut self, rect: &FloatRect, color: Color) {
+        if let Some(skia_rect) = skia_rect(rect) {
+            self.pixmap.fill_rect(skia_rect, &paint_for_color(color), self.transform, self.clip.as_ref());
+        }
+    }
+
+    pub fn stroke_rect(&mut self, rect: &FloatRect, stroke: &Stroke) {
+        if let Some(skia_rect) = skia_rect(rect) {
+            let path = tiny_skia::PathBuilder::from_rect(skia_rect);
+            self.stroke_path(&path, stroke);
+        }
+    }
+
+    pub fn fill_ellipse(&mut self, rect: &FloatRect, color: Color) {
+        if let Some(path) = skia_rect(rect).and_then(tiny_skia::PathBuilder::from_oval) {
+            self.pixmap.fill_path(&path, &paint_for_color(color), tiny_skia::FillRule::Winding, self.transform, self.clip.as_ref());
+        }
+    }
+
+    pub fn stroke_line(&mut self, from: &FloatPos, to: &FloatPos, stroke: &Stroke) {
+        let mut builder = tiny_skia::PathBuilder::new();
+        builder.move_to(from.x, from.y);
+        builder.line_to(to.x, to.y);
+        if let Some(path) = builder.finish() {
+            self.stroke_path(&path, stroke);
+        }
+    }
+
+    pub fn stroke_polyline(&mut self, points: &[FloatPos], stroke: &Stroke) {
+        if let Some(path) = polygon_path(points, false) {
+            self.stroke_path(&path, stroke);
+        }
+    }
+
+    pub fn fill_polygon(&mut self, points: &[FloatPos], color: Color) {
+        if let Some(path) = polygon_path(points, true) {
+            self.pixmap.fill_path(&path, &paint_for_color(color), tiny_skia::FillRule::Winding, self.transform, self.clip.as_ref());
+        }
+    }
+
+    /// Width of a line of text drawn by `draw_text`.
+    pub fn text_width(&self, text: &str, height: f32) -> f32 {
+        let scale = rusttype::Scale::uniform(height);
+        self.font
+            .layout(text, scale, point(0.0, 0.0))
+            .last()
+            .map(|glyph| glyph.position().x + glyph.unpositioned().h_metrics().advance_width)
+            .unwrap_or(0.0)
+    }
+
+    /// Draws a line of text in the default font with its top left corner at `pos`.
+    pub fn draw_text(&mut self, text: &str, pos: &FloatPos, height: f32, color: Color) {
+        let scale = rusttype::Scale::uniform(height);
+        let ascent = self.font.v_metrics(scale).ascent;
+        let glyphs: Vec<PositionedGlyph> = self.font.layout(text, scale, point(0.0, ascent)).collect();
+        let bounds = glyphs.iter().filter_map(|glyph| glyph.pixel_bounding_box()).fold(None, |bounds: Option<rusttype::Rect<i32>>, bb| {
+            Some(match bounds {
+                Some(bounds) => rusttype::Rect {
+                    min: point(bounds.min.x.min(bb.min.x), bounds.min.y.min(bb.min.y)),
+                    max: point(bounds.max.x.max(bb.max.x), bounds.max.y.max(bb.max.y)),
+                },
+                None => bb,
+            })
+        });
+        let bounds = match bounds {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let mut text_pixmap = match tiny_skia::Pixmap::new(bounds.width() as u32, bounds.height() as u32) {
+            Some(text_pixmap) => text_pixmap,
+            None => return,
+        };
+        let (r, g, b, a) = color.rgba();
+        let text_width = bounds.width();
+        let pixels = text_pixmap.pixels_mut();
+        for glyph in &glyphs {
+            if let Some(bb) = glyph.pixel_bounding_box() {
+                glyph.draw(|gx, gy, coverage| {
+                    let x = gx as i32 + bb.min.x - bounds.min.x;
+                    let y = gy as i32 + bb.min.y - bounds.min.y;
+                    let alpha = (coverage * a as f32) as u8;
+                    let premultiply = |channel: u8| (channel as u32 * alpha as u32 / 255) as u8;
+                    if let Some(color) = tiny_skia::PremultipliedColorU8::from_rgba(premultiply(r), premultiply(g), premultiply(b), alpha) {
+                        pixels[(y * text_width + x) as usize] = color;
+                    }
+                });
+            }
+        }
+        let transform = self.transform.pre_translate(pos.x, pos.y);
+        self.pixmap.draw_pixmap(bounds.min.x, bounds.min.y, text_pixmap.as_ref(), &tiny_skia::PixmapPaint::default(), transform, self.clip.as_ref());
+    }
+
+    /// Draws the image stretched to `rect`.
+    pub fn draw_image(&mut self, image: &RgbaImage, rect: &FloatRect) {
+        let skia_rect = match skia_rect(rect) {
+            Some(skia_rect) => skia_rect,
+            None => return,
+        };
+        let mut image_pixmap = match tiny_skia::Pixmap::new(image.width(), image.height()) {
+            Some(image_pixmap) => image_pixmap,
+            None => return,
+        };
+        for (dst, rgba) in image_pixmap.pixels_mut().iter_mut().zip(image.pixels()) {
+            let [r, g, b, a] = rgba.data;
+            *dst = tiny_skia::ColorU8::from_rgba(r, g, b, a).premultiply();
+        }
+        let pattern_transform = tiny_skia::Transform::from_row(
+            rect.size.width / image.width() as f32,
+            0.0,
+            0.0,
+            rect.size.height / image.height() as f32,
+            rect.pos.x,
+            rect.pos.y,
+        );
+        let paint = tiny_skia::Paint {
+            shader: tiny_skia::Pattern::new(
+                image_pixmap.as_ref(),
+                tiny_skia::SpreadMode::Pad,
+                tiny_skia::FilterQuality::Bilinear,
+                1.0,
+                pattern_transform,
+            ),
+            ..tiny_skia::Paint::default()
+        };
+        self.pixmap.fill_rect(skia_rect, &paint, self.transform, self.clip.as_ref());
+    }
+
+    /// Limits drawing to `rect`, intersected with the current clip.
+    pub fn clip_rect(&mut self, rect: &FloatRect) {
+        let path = match skia_rect(rect) {
+            Some(skia_rect) => tiny_skia::PathBuilder::from_rect(skia_rect),
+            None => {
+                // Empty clip, nothing is drawn until restored.
+                self.clip = tiny_skia::Mask::new(self.pixmap.width(), self.pixmap.height());
+                return;
+            }
+        };
+        match self.clip {
+            Some(ref mut clip) => clip.intersect_path(&path, tiny_skia::FillRule::Winding, true, self.transform),
+            None => {
+                if let Some(mut clip) = tiny_skia::Mask::new(self.pixmap.width(), self.pixmap.height()) {
+                    clip.fill_path(&path, tiny_skia::FillRule::Winding, true, self.transform);
+                    self.clip = Some(clip);
+                }
+            }
+        }
+    }
+
+    pub fn translate(&mut self, x: f32, y: f32) {
+        self.transform = self.transform.pre_translate(x, y);
+    }
+
+    pub fn save(&mut self) {
+        self.saved.push((self.transform, self.clip.clone()));
+    }
+
+    pub fn restore(&mut self) {
+        if let Some((transform, clip)) = self.saved.pop() {
+            self.transform = transform;
+            self.clip = clip;
+        }
+    }
+
+    fn stroke_path(&mut self, path: &tiny_skia::Path, stroke: &Stroke) {
+        self.pixmap.stroke_path(path, &paint_for_color(stroke.color), &skia_stroke(stroke), self.transform, self.clip.as_ref());
+    }
+}
+
+/// Marks a `CanvasSprite` for drawing from anywhere, e.g. from the state its closure reads.
+#[derive(Clone)]
+pub struct CanvasInvalidator(Rc<Cell<bool>>);
+
+impl CanvasInvalidator {
+    pub fn invalidate(&self) {
+        self.0.set(true);
+    }
+}
+
+/// Sprite drawn by a closure. The closure runs when the node is laid out and after the sprite
+/// is invalidated, otherwise the last drawing is kept.
+pub struct CanvasSprite {
+    pub font: Font<'static>,
+    on_draw: Box<dyn FnMut(&mut Painter)>,
+    invalidated: Rc<Cell<bool>>,
+    raw_pixels: Vec<u32>,
+    frame: Rect,
+}
+
+impl CanvasSprite {
+    pub fn new<F>(on_draw: F) -> CanvasSprite
+    where
+        F: FnMut(&mut Painter) + 'static,
+    {
+        CanvasSprite {
+            font: FontCollection::from_bytes(get_default_font_data()).unwrap().into_font().unwrap(),
+            on_draw: Box::new(on_draw),
+            invalidated: Rc::new(Cell::new(false)),
+            raw_pixels: Vec::new(),
+            frame: RECT_ZERO,
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.invalidated.set(true);
+    }
+
+    pub fn invalidator(&self) -> CanvasInvalidator {
+        CanvasInvalidator(self.invalidated.clone())
+    }
+}
+
+impl<'a> Sprite<'a> for CanvasSprite {
+    fn draw(&mut self, outer_rect: &Rect, _screen_info: &ScreenInfo) {
+        self.invalidated.set(false);
+        let mut pixmap = match tiny_skia::Pixmap::new(outer_rect.size.width as u32, outer_rect.size.height as u32) {
+            Some(pixmap) => pixmap,
+            None => {
+                self.raw_pixels.clear();
+                self.frame = RECT_ZERO;
+                return;
+            }
+        };
+        {
+            let mut painter = Painter::new(&mut pixmap, &self.font);
+            (self.on_draw)(&mut painter);
+        }
+        self.raw_pixels = pixels_from_pixmap(&pixmap);
+        self.frame = Rect { pos: POS_ZERO, size: outer_rect.size };
+    }
+
+    fn pixels(&self) -> (&[u32], &Rect) {
+        (&self.raw_pixels, &self.frame)
+    }
+
+    fn update(&mut self, _clock: time::Duration) -> bool {
+        self.invalidated.get()
+    }
+}
+
+fn skia_rect(rect: &FloatRect) -> Option<tiny_skia::Rect> {
+    tiny_skia::Rect::from_xywh(rect.pos.x, rect.pos.y, rect.size.width, rect.size.height)
+}
+
+fn polygon_path(points: &[FloatPos], close: bool) -> Option<tiny_skia::Path> {
+    let mut builder = tiny_skia::PathBuilder::new();
+    for (index, point) in points.iter().enumerate() {
+        if index == 0 {
+            builder.move_to(point.x, point.y);
+        } else {
+            builder.line_to(point.x, point.y);
+        }
+    }
+    if close {
+        builder.close();
+    }
+    builder.finish()
+}
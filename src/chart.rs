@@ -0,0 +1,360 @@
+extern crate rusttype;
+extern crate tiny_skia;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time;
+
+use rusttype::{Font, FontCollection};
+
+use canvas_sprite::Painter;
+use color::{self, Color};
+use dimension::*;
+use screen_writer::ScreenInfo;
+use shape::{LineCap, LineJoin, Stroke};
+use sprite::{pixels_from_pixmap, Sprite};
+use text::get_default_font_data;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChartKind {
+    Line,
+    /// Line with the area below it filled.
+    Area,
+    Bar,
+    /// Bare line without grid, axis or labels, ending in a dot at the latest value.
+    Sparkline,
+}
+
+struct SampleBuffer {
+    values: VecDeque<f32>,
+    capacity: usize,
+    revision: u64,
+}
+
+/// Ring buffer of chart values shared by a `ChartSprite` and whoever feeds it. Once full,
+/// every new sample drops the oldest one.
+#[derive(Clone)]
+pub struct ChartSamples(Rc<RefCell<SampleBuffer>>);
+
+impl ChartSamples {
+    pub fn new(capacity: usize) -> ChartSamples {
+        ChartSamples(Rc::new(RefCell::new(SampleBuffer {
+            values: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            revision: 0,
+        })))
+    }
+
+    pub fn push(&self, value: f32) {
+        self.extend(&[value]);
+    }
+
+    pub fn extend(&self, values: &[f32]) {
+        let mut buffer = self.0.borrow_mut();
+        for &value in values {
+            if buffer.values.len() == buffer.capacity {
+                buffer.values.pop_front();
+            }
+            buffer.values.push_back(value);
+        }
+        buffer.revision += 1;
+    }
+
+    pub fn clear(&self) {
+        let mut buffer = self.0.borrow_mut();
+        buffer.values.clear();
+        buffer.revision += 1;
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.0.borrow().capacity
+    }
+
+    /// Changes the number of samples kept, dropping the oldest ones that no longer fit.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut buffer = self.0.borrow_mut();
+        buffer.capacity = capacity.max(1);
+        while buffer.values.len() > buffer.capacity {
+            buffer.values.pop_front();
+        }
+        buffer.revision += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().values.is_empty()
+    }
+
+    /// Samples from the oldest to the latest.
+    pub fn values(&self) -> Vec<f32> {
+        self.0.borrow().values.iter().cloned().collect()
+    }
+
+    pub fn last(&self) -> Option<f32> {
+        self.0.borrow().values.back().cloned()
+    }
+
+    fn revision(&self) -> u64 {
+        self.0.borrow().revision
+    }
+}
+
+/// Chart of the samples in a `ChartSamples` buffer. The latest sample is at the right edge and
+/// the buffer capacity spans the plot width. The chart is only drawn again when samples change.
+pub struct ChartSprite {
+    pub kind: ChartKind,
+    /// Value at the bottom of the plot, the smallest sample when None.
+    pub min: Option<f32>,
+    /// Value at the top of the plot, the largest sample when None.
+    pub max: Option<f32>,
+    pub color: Color,
+    pub line_width: f32,
+    /// Area and bar fill, the line color at a third of its alpha when None.
+    pub fill: Option<Color>,
+    pub background: Option<Color>,
+    pub grid: Option<Stroke>,
+    /// Horizontal grid divisions, also where the value labels go.
+    pub grid_rows: u32,
+    /// Vertical grid divisions.
+    pub grid_columns: u32,
+    /// Left and bottom axis lines.
+    pub axis: Option<Stroke>,
+    /// Color of the value labels left of the plot, None hides them.
+    pub labels: Option<Color>,
+    pub label_size: f32,
+    pub font: Font<'static>,
+    samples: ChartSamples,
+    drawn_revision: u64,
+    raw_pixels: Vec<u32>,
+    frame: Rect,
+}
+
+impl ChartSprite {
+    pub fn new(kind: ChartKind, capacity: usize) -> ChartSprite {
+        ChartSprite {
+            kind,
+            min: None,
+            max: None,
+            color: color::BLACK,
+            line_width: 2.0,
+            fill: None,
+            background: None,
+            grid: None,
+            grid_rows: 4,
+            grid_columns: 0,
+            axis: None,
+            labels: None,
+            label_size: 14.0,
+            font: FontCollection::from_bytes(get_default_font_data()).unwrap().into_font().unwrap(),
+            samples: ChartSamples::new(capacity),
+            drawn_revision: 0,
+            raw_pixels: Vec::new(),
+            frame: RECT_ZERO,
+        }
+    }
+
+    /// Handle to feed the chart from elsewhere.
+    pub fn samples(&self) -> ChartSamples {
+        self.samples.clone()
+    }
+
+    pub fn push(&mut self, value: f32) {
+        self.samples.push(value);
+    }
+
+    /// Plotted value range, from `min` and `max` or the samples. Bars and areas keep zero in
+    /// an automatic range, so they grow from a baseline.
+    fn range(&self, values: &[f32]) -> (f32, f32) {
+        let with_zero = self.kind == ChartKind::Bar || self.kind == ChartKind::Area;
+        let sample_min = values.iter().cloned().fold(f32::INFINITY, f32::min);
+        let sample_max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let (sample_min, sample_max) = if values.is_empty() {
+            (0.0, 1.0)
+        } else if with_zero {
+            (sample_min.min(0.0), sample_max.max(0.0))
+        } else {
+            (sample_min, sample_max)
+        };
+        let min = self.min.unwrap_or(sample_min);
+        let max = self.max.unwrap_or(sample_max);
+        if max > min {
+            (min, max)
+        } else {
+            (min - 0.5, min + 0.5)
+        }
+    }
+
+    fn paint(&self, painter: &mut Painter) {
+        let values = self.samples.values();
+        let capacity = self.samples.capacity();
+        let (min, max) = self.range(&values);
+        let sparkline = self.kind == ChartKind::Sparkline;
+        let grid_rows = self.grid_rows.max(1);
+
+        let label_texts: Vec<(f32, String)> = match self.labels {
+            Some(_) if !sparkline => {
+                let step = (max - min) / grid_rows as f32;
+                // Enough decimals to tell the grid steps apart exactly.
+                let decimals = (0..4)
+                    .find(|&decimals| {
+                        let scaled = step * 10f32.powi(decimals);
+                        (scaled - scaled.round()).abs() < 0.01
+                    })
+                    .unwrap_or(4) as usize;
+                (0..=grid_rows)
+                    .map(|row| {
+                        let value = max - step * row as f32;
+                        (value, format!("{:.*}", decimals, value))
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let gutter = label_texts
+            .iter()
+            .map(|(_, text)| painter.text_width(text, self.label_size) + self.label_size / 2.0)
+            .fold(0.0, f32::max);
+        let padding = if sparkline {
+            self.line_width * 2.0
+        } else if label_texts.is_empty() {
+            self.line_width / 2.0
+        } else {
+            self.label_size / 2.0
+        };
+        let plot = FloatRect {
+            pos: FloatPos {
+                x: gutter.max(padding),
+                y: padding,
+            },
+            size: FloatSize {
+                width: painter.width() - gutter.max(padding) - padding,
+                height: painter.height() - padding * 2.0,
+            },
+        };
+        if plot.size.width <= 0.0 || plot.size.height <= 0.0 {
+            return;
+        }
+        let value_y = |value: f32| plot.pos.y + plot.size.height * (1.0 - (value - min) / (max - min));
+
+        if let Some(background) = self.background {
+            painter.clear(background);
+        }
+        if let Some(grid) = self.grid.filter(|_| !sparkline) {
+            for row in 0..=grid_rows {
+                let y = plot.pos.y + plot.size.height * row as f32 / grid_rows as f32;
+                painter.stroke_line(&FloatPos { x: plot.pos.x, y }, &FloatPos { x: plot.pos.x + plot.size.width, y }, &grid);
+            }
+            for column in 0..=self.grid_columns {
+                let x = plot.pos.x + plot.size.width * column as f32 / self.grid_columns.max(1) as f32;
+                painter.stroke_line(&FloatPos { x, y: plot.pos.y }, &FloatPos { x, y: plot.pos.y + plot.size.height }, &grid);
+            }
+        }
+
+        let fill = self.fill.unwrap_or_else(|| {
+            let (_, _, _, alpha) = self.color.rgba();
+            self.color.color_with_alpha_float(alpha as f32 / 255.0 / 3.0)
+        });
+        let baseline = value_y(0.0f32.max(min).min(max));
+        // Samples fill the slots of the buffer capacity from the right.
+        let first_slot = capacity.saturating_sub(values.len());
+        painter.save();
+        painter.clip_rect(&FloatRect {
+            pos: FloatPos { x: plot.pos.x - padding, y: plot.pos.y - padding },
+            size: FloatSize { width: plot.size.width + padding * 2.0, height: plot.size.height + padding * 2.0 },
+        });
+        if self.kind == ChartKind::Bar {
+            let slot_width = plot.size.width / capacity as f32;
+            for (index, &value) in values.iter().enumerate() {
+                let y = value_y(value);
+                let bar = FloatRect {
+                    pos: FloatPos {
+                        x: plot.pos.x + slot_width * ((first_slot + index) as f32 + 0.1),
+                        y: y.min(baseline),
+                    },
+                    size: FloatSize {
+                        width: slot_width * 0.8,
+                        height: (y - baseline).abs(),
+                    },
+                };
+                painter.fill_rect(&bar, fill);
+            }
+        } else if !values.is_empty() {
+            let slot_width = plot.size.width / (capacity.max(2) - 1) as f32;
+            let points: Vec<FloatPos> = values
+                .iter()
+                .enumerate()
+                .map(|(index, &value)| FloatPos {
+                    x: plot.pos.x + slot_width * (first_slot + index) as f32,
+                    y: value_y(value),
+                })
+                .collect();
+            if self.kind == ChartKind::Area {
+                let mut area = points.clone();
+                area.push(FloatPos { x: points[points.len() - 1].x, y: baseline });
+                area.push(FloatPos { x: points[0].x, y: baseline });
+                painter.fill_polygon(&area, fill);
+            }
+            let mut stroke = Stroke::new(self.color, self.line_width);
+            stroke.join = LineJoin::Round;
+            stroke.cap = LineCap::Round;
+            painter.stroke_polyline(&points, &stroke);
+            if sparkline {
+                let last = points[points.len() - 1];
+                let radius = self.line_width * 1.5;
+                painter.fill_ellipse(
+                    &FloatRect {
+                        pos: FloatPos { x: last.x - radius, y: last.y - radius },
+                        size: FloatSize { width: radius * 2.0, height: radius * 2.0 },
+                    },
+                    self.color,
+                );
+            }
+        }
+        painter.restore();
+
+        if let Some(axis) = self.axis.filter(|_| !sparkline) {
+            let bottom = plot.pos.y + plot.size.height;
+            painter.stroke_line(&plot.pos, &FloatPos { x: plot.pos.x, y: bottom }, &axis);
+            painter.stroke_line(&FloatPos { x: plot.pos.x, y: bottom }, &FloatPos { x: plot.pos.x + plot.size.width, y: bottom }, &axis);
+        }
+        if let Some(label_color) = self.labels {
+            for (value, text) in &label_texts {
+                let width = painter.text_width(text, self.label_size);
+                let pos = FloatPos {
+                    x: plot.pos.x - self.label_size / 4.0 - width,
+                    y: value_y(*value) - self.label_size / 2.0,
+                };
+                painter.draw_text(text, &pos, self.label_size, label_color);
+            }
+        }
+    }
+}
+
+impl<'a> Sprite<'a> for ChartSprite {
+    fn draw(&mut self, outer_rect: &Rect, _screen_info: &ScreenInfo) {
+        self.drawn_revision = self.samples.revision();
+        let mut pixmap = match tiny_skia::Pixmap::new(outer_rect.size.width as u32, outer_rect.size.height as u32) {
+            Some(pixmap) => pixmap,
+            None => {
+                self.raw_pixels.clear();
+                self.frame = RECT_ZERO;
+                return;
+            }
+        };
+        self.paint(&mut Painter::new(&mut pixmap, &self.font));
+        self.raw_pixels = pixels_from_pixmap(&pixmap);
+        self.frame = Rect { pos: POS_ZERO, size: outer_rect.size };
+    }
+
+    fn pixels(&self) -> (&[u32], &Rect) {
+        (&self.raw_pixels, &self.frame)
+    }
+
+    fn update(&mut self, _clock: time::Duration) -> bool {
+        self.samples.revision() != self.drawn_revision
+    }
+}
@@ -0,0 +1,81 @@
+extern crate chrono;
+
+use chrono::{FixedOffset, Local, Utc};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TimeZone {
+    Local,
+    Utc,
+    /// Fixed offset east of UTC in seconds.
+    Offset(i32),
+}
+
+impl TimeZone {
+    /// Parses `local`, `utc` or an offset like `+02:00`, `-0530` or `+3`.
+    pub fn parse(value: &str) -> Option<TimeZone> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("local") {
+            return Some(TimeZone::Local);
+        }
+        if value.eq_ignore_ascii_case("utc") || value.eq_ignore_ascii_case("gmt") || value == "Z" {
+            return Some(TimeZone::Utc);
+        }
+        let sign = if value.starts_with('+') {
+            1
+        } else if value.starts_with('-') {
+            -1
+        } else {
+            return None;
+        };
+        let digits: String = value[1..].chars().filter(|&c| c != ':').collect();
+        if digits.is_empty() || digits.len() > 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let (hours, minutes) = if digits.len() > 2 {
+            digits.split_at(digits.len() - 2)
+        } else {
+            (digits.as_str(), "0")
+        };
+        let seconds = hours.parse::<i32>().ok()? * 3600 + minutes.parse::<i32>().ok()? * 60;
+        if seconds > 24 * 3600 {
+            return None;
+        }
+        Some(TimeZone::Offset(sign * seconds))
+    }
+}
+
+/// Current date and time formatted for a `TextSprite`, which updates its text whenever the
+/// formatted string changes.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    /// strftime style format, e.g. `%H:%M:%S` or `%A, %d %B %Y`.
+    pub format: String,
+    pub time_zone: TimeZone,
+}
+
+impl Clock {
+    pub fn new(format: &str, time_zone: TimeZone) -> Clock {
+        Clock {
+            format: format.to_string(),
+            time_zone,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        use std::fmt::Write;
+        let mut text = String::new();
+        // An invalid format fails to write, leaving the text empty instead of panicking.
+        let result = match self.time_zone {
+            TimeZone::Local => write!(text, "{}", Local::now().format(&self.format)),
+            TimeZone::Utc => write!(text, "{}", Utc::now().format(&self.format)),
+            TimeZone::Offset(seconds) => match FixedOffset::east_opt(seconds) {
+                Some(offset) => write!(text, "{}", Utc::now().with_timezone(&offset).format(&self.format)),
+                None => write!(text, "{}", Utc::now().format(&self.format)),
+            },
+        };
+        if result.is_err() {
+            text.clear();
+        }
+        text
+    }
+}
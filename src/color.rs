@@ -1,7 +1,7 @@
 use std::u32;
 
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Color(pub u32);
 
 pub struct PredefinedColor {
@@ -55,6 +55,16 @@ impl Color {
     pub fn color_with_alpha_float(&self, alpha:f32) -> Color {
         return self.color_with_alpha((0xFF as f32 * alpha) as u8);
     }
+
+    pub fn from_rgba(r:u8, g:u8, b:u8, a:u8) -> Color {
+        Color((r as u32) | ((g as u32) << 8) | ((b as u32) << 16) | ((a as u32) << 24))
+    }
+
+    /// Red, green, blue and alpha components.
+    pub fn rgba(&self) -> (u8, u8, u8, u8) {
+        let Color(c) = *self;
+        (c as u8, (c >> 8) as u8, (c >> 16) as u8, (c >> 24) as u8)
+    }
 }
 
 
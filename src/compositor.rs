@@ -0,0 +1,177 @@
+extern crate rayon;
+
+use dimension::*;
+use screen_writer::ScreenInfo;
+use sprite::{intersects, render_to_canvas, render_to_canvas_transformed, RenderItem};
+
+/// Number of bands every compositor thread gets on average, more bands balance the load
+/// better when the scene is busier in some parts of the screen.
+const BANDS_PER_THREAD: i32 = 4;
+
+#[derive(Copy, Clone)]
+struct CanvasPtr(*mut u32);
+
+// Every band writes to its own rows of the canvas.
+unsafe impl Send for CanvasPtr {}
+unsafe impl Sync for CanvasPtr {}
+
+/// Composites the items in order onto the part of the canvas covered by `region`.
+pub fn composite(items: &[RenderItem], region: &Rect, screen_info: &ScreenInfo, canvas_ptr: *mut u32) {
+    for item in items {
+        if !intersects(&item.bounds(), region) {
+            continue;
+        }
+        if item.is_transformed() {
+            render_to_canvas_transformed(item, region, screen_info, canvas_ptr);
+        } else {
+            render_to_canvas(item, region, screen_info, canvas_ptr);
+        }
+    }
+}
+
+/// Splits the canvas into horizontal bands and composites them on the thread pool. Each band
+/// blends the same items in the same order as `composite`, so the output is identical.
+pub fn composite_parallel(
+    thread_pool: &rayon::ThreadPool,
+    items: &[RenderItem],
+    screen_info: &ScreenInfo,
+    canvas_ptr: *mut u32,
+) {
+    let band_count = (thread_pool.current_num_threads() as i32 * BANDS_PER_THREAD).max(1);
+    let band_height = (screen_info.yres + band_count - 1) / band_count;
+    let canvas = CanvasPtr(canvas_ptr);
+
+    thread_pool.scope(|scope| {
+        for band in 0..band_count {
+            let region = Rect {
+                pos: Pos {
+                    x: 0,
+                    y: band * band_height,
+                },
+                size: Size {
+                    width: screen_info.xres,
+                    height: band_height,
+                },
+            };
+            scope.spawn(move |_| {
+                let canvas = canvas;
+                composite(items, &region, screen_info, canvas.0);
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mask::{Mask, MaskShape};
+    use screen_writer::{PixelDef, PIXEL_RGBA};
+    use transform::{Transform, TRANSFORM_IDENTITY};
+
+    const WIDTH: i32 = 64;
+    const HEIGHT: i32 = 50;
+
+    fn screen_info() -> ScreenInfo {
+        ScreenInfo {
+            xres: WIDTH,
+            yres: HEIGHT,
+            screen_size: (WIDTH * HEIGHT * 4) as usize,
+            pixel_def: PixelDef {
+                bits_per_pixel: PIXEL_RGBA.bits_per_pixel,
+                bytes_per_pixel: PIXEL_RGBA.bytes_per_pixel,
+                red_offset: PIXEL_RGBA.red_offset,
+                green_offset: PIXEL_RGBA.green_offset,
+                blue_offset: PIXEL_RGBA.blue_offset,
+                transp_offset: PIXEL_RGBA.transp_offset,
+            },
+            show_debug_info: false,
+        }
+    }
+
+    fn gradient(size: &Size, alpha: u32) -> Vec<u32> {
+        let mut pixels = Vec::new();
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let red = (x * 255 / size.width) as u32;
+                let green = (y * 255 / size.height) as u32;
+                pixels.push(alpha << 24 | 0x60 << 16 | green << 8 | red);
+            }
+        }
+        pixels
+    }
+
+    fn render_item(pixels: &[u32], frame: Rect, transform: Transform) -> RenderItem {
+        RenderItem {
+            raw_pixels_ptr: pixels.as_ptr(),
+            parent_node_frame: Rect {
+                pos: Pos { x: 0, y: 0 },
+                size: Size {
+                    width: WIDTH,
+                    height: HEIGHT,
+                },
+            },
+            parent_transform: TRANSFORM_IDENTITY,
+            outer_frame: frame,
+            inner_frame: Rect {
+                pos: Pos { x: 0, y: 0 },
+                size: frame.size,
+            },
+            transform,
+            clip_to_bounds: true,
+            masks: Vec::new(),
+        }
+    }
+
+    fn frame(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            pos: Pos { x, y },
+            size: Size { width, height },
+        }
+    }
+
+    #[test]
+    fn parallel_matches_single_threaded() {
+        let screen_info = screen_info();
+        let opaque_frame = frame(0, 0, 40, 30);
+        let blended_frame = frame(20, 10, 40, 30);
+        let masked_frame = frame(8, 16, 36, 28);
+        let rotated_frame = frame(16, 8, 32, 32);
+        let opaque = gradient(&opaque_frame.size, 0xFF);
+        let blended = gradient(&blended_frame.size, 0x80);
+        let masked = gradient(&masked_frame.size, 0xC0);
+        let rotated = gradient(&rotated_frame.size, 0xA0);
+
+        let mut ellipse = Mask::new(MaskShape::Ellipse);
+        ellipse.draw(&masked_frame.size);
+        let mut rounded_rect = Mask::new(MaskShape::RoundedRect(8.0));
+        rounded_rect.draw(&rotated_frame.size);
+        let rotation = Transform::translation(32.0, 24.0)
+            .multiply(&Transform::rotation(30.0))
+            .multiply(&Transform::translation(-32.0, -24.0));
+
+        let mut masked_item = render_item(&masked, masked_frame, TRANSFORM_IDENTITY);
+        masked_item.masks.push(ellipse.clip(&masked_frame, &TRANSFORM_IDENTITY));
+        let mut rotated_item = render_item(&rotated, rotated_frame, rotation);
+        rotated_item.clip_to_bounds = false;
+        rotated_item.masks.push(rounded_rect.clip(&rotated_frame, &rotation));
+        let items = vec![
+            render_item(&opaque, opaque_frame, TRANSFORM_IDENTITY),
+            render_item(&blended, blended_frame, TRANSFORM_IDENTITY),
+            masked_item,
+            rotated_item,
+        ];
+
+        let screen = frame(0, 0, WIDTH, HEIGHT);
+        let mut expected = vec![0xFF20_2020u32; (WIDTH * HEIGHT) as usize];
+        composite(&items, &screen, &screen_info, expected.as_mut_ptr());
+        assert!(expected.iter().any(|pixel| *pixel != 0xFF20_2020));
+
+        for threads in 2..5 {
+            let thread_pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let mut canvas = vec![0xFF20_2020u32; (WIDTH * HEIGHT) as usize];
+            composite_parallel(&thread_pool, &items, &screen_info, canvas.as_mut_ptr());
+            assert!(canvas == expected, "{} threads", threads);
+        }
+    }
+}
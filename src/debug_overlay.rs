@@ -0,0 +1,239 @@
+extern crate rusttype;
+extern crate tiny_skia;
+
+use std::collections::VecDeque;
+use std::time;
+
+use rusttype::{point, Font, FontCollection, PositionedGlyph};
+
+use color;
+use color::Color;
+use dimension::*;
+use screen_writer::ScreenInfo;
+use shape::paint_for_color;
+use sprite::{pixels_from_pixmap, RenderItem};
+use text::get_default_font_data;
+use transform::TRANSFORM_IDENTITY;
+
+const FRAME_HISTORY: usize = 120;
+const LABEL_HEIGHT: f32 = 14.0;
+const GRAPH_WIDTH: f32 = 240.0;
+const GRAPH_HEIGHT: f32 = 80.0;
+const GRAPH_MARGIN: f32 = 8.0;
+/// Frame time shown at the top of the graph, in milliseconds.
+const GRAPH_SCALE_MS: f32 = 50.0;
+
+/// What the overlay shows for a visible node.
+pub struct DebugNodeInfo {
+    pub tag: String,
+    pub sprite_type: &'static str,
+    pub frame: Rect,
+    /// Screen bounds of the node after its transform.
+    pub bounds: Rect,
+    pub redrawn: bool,
+}
+
+/// Draws node tags, frames and sprite types on top of the scene, highlights nodes redrawn in
+/// the current frame and shows an FPS counter with a frame time graph in the top right corner.
+pub struct DebugOverlay {
+    font: Font<'static>,
+    frame_times: VecDeque<time::Duration>,
+    frame_intervals: VecDeque<time::Duration>,
+    last_frame_start: Option<time::Instant>,
+    raw_pixels: Vec<u32>,
+    frame: Rect,
+}
+
+impl DebugOverlay {
+    pub fn new() -> DebugOverlay {
+        DebugOverlay {
+            font: FontCollection::from_bytes(get_default_font_data()).unwrap().into_font().unwrap(),
+            frame_times: VecDeque::with_capacity(FRAME_HISTORY),
+            frame_intervals: VecDeque::with_capacity(FRAME_HISTORY),
+            last_frame_start: None,
+            raw_pixels: Vec::new(),
+            frame: RECT_ZERO,
+        }
+    }
+
+    pub fn begin_frame(&mut self, now: time::Instant) {
+        if let Some(last_frame_start) = self.last_frame_start {
+            push_limited(&mut self.frame_intervals, now.duration_since(last_frame_start));
+        }
+        self.last_frame_start = Some(now);
+    }
+
+    pub fn end_frame(&mut self, frame_time: time::Duration) {
+        push_limited(&mut self.frame_times, frame_time);
+    }
+
+    pub fn fps(&self) -> f32 {
+        let total: f32 = self.frame_intervals.iter().map(duration_ms).sum();
+        if total > 0.0 {
+            self.frame_intervals.len() as f32 * 1000.0 / total
+        } else {
+            0.0
+        }
+    }
+
+    pub fn draw(&mut self, nodes: &[DebugNodeInfo], screen_info: &ScreenInfo) {
+        self.frame = Rect {
+            pos: POS_ZERO,
+            size: Size {
+                width: screen_info.xres,
+                height: screen_info.yres,
+            },
+        };
+        let mut pixmap = match tiny_skia::Pixmap::new(screen_info.xres as u32, screen_info.yres as u32) {
+            Some(pixmap) => pixmap,
+            None => return,
+        };
+
+        for node in nodes {
+            self.draw_node(&mut pixmap, node);
+        }
+        self.draw_graph(&mut pixmap, screen_info);
+
+        self.raw_pixels = pixels_from_pixmap(&pixmap);
+    }
+
+    pub fn render(&self, items: &mut Vec<RenderItem>) {
+        items.push(RenderItem {
+            raw_pixels_ptr: self.raw_pixels.as_ptr(),
+            parent_node_frame: self.frame,
+            parent_transform: TRANSFORM_IDENTITY,
+            outer_frame: self.frame,
+            inner_frame: self.frame,
+            transform: TRANSFORM_IDENTITY,
+            clip_to_bounds: true,
+            masks: Vec::new(),
+        });
+    }
+
+    fn draw_node(&self, pixmap: &mut tiny_skia::Pixmap, node: &DebugNodeInfo) {
+        let bounds = match to_skia_rect(&node.bounds) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let outline_color = if node.redrawn { color::RED } else { Color(0xFFFFFF00) };
+        if node.redrawn {
+            pixmap.fill_rect(bounds, &paint_for_color(color::RED.color_with_alpha(0x30)), tiny_skia::Transform::identity(), None);
+        }
+        let path = tiny_skia::PathBuilder::from_rect(bounds);
+        let stroke = tiny_skia::Stroke {
+            width: 1.0,
+            ..tiny_skia::Stroke::default()
+        };
+        pixmap.stroke_path(&path, &paint_for_color(outline_color), &stroke, tiny_skia::Transform::identity(), None);
+
+        let mut label = String::new();
+        if !node.tag.is_empty() {
+            label.push_str(&node.tag);
+            label.push(' ');
+        }
+        label.push_str(&format!(
+            "{} {},{} {}x{}",
+            node.sprite_type, node.frame.pos.x, node.frame.pos.y, node.frame.size.width, node.frame.size.height
+        ));
+        self.draw_label(pixmap, &label, bounds.left() + 2.0, bounds.top() + 2.0, outline_color);
+    }
+
+    fn draw_graph(&self, pixmap: &mut tiny_skia::Pixmap, screen_info: &ScreenInfo) {
+        let left = screen_info.xres as f32 - GRAPH_WIDTH - GRAPH_MARGIN;
+        let top = GRAPH_MARGIN;
+        let background = match tiny_skia::Rect::from_xywh(left, top, GRAPH_WIDTH, GRAPH_HEIGHT) {
+            Some(background) => background,
+            None => return,
+        };
+        pixmap.fill_rect(background, &paint_for_color(color::BLACK.color_with_alpha(0xB0)), tiny_skia::Transform::identity(), None);
+
+        // Guide line for a 60 FPS frame budget.
+        let budget_y = top + GRAPH_HEIGHT - GRAPH_HEIGHT * (1000.0 / 60.0) / GRAPH_SCALE_MS;
+        if let Some(budget) = tiny_skia::Rect::from_xywh(left, budget_y, GRAPH_WIDTH, 1.0) {
+            pixmap.fill_rect(budget, &paint_for_color(color::GRAY), tiny_skia::Transform::identity(), None);
+        }
+
+        let bar_width = GRAPH_WIDTH / FRAME_HISTORY as f32;
+        for (index, frame_time) in self.frame_times.iter().enumerate() {
+            let ms = duration_ms(frame_time);
+            let height = (GRAPH_HEIGHT * ms / GRAPH_SCALE_MS).clamp(1.0, GRAPH_HEIGHT);
+            let bar_color = if ms > 1000.0 / 30.0 {
+                color::RED
+            } else if ms > 1000.0 / 60.0 {
+                color::YELLOW
+            } else {
+                color::LIME
+            };
+            let x = left + GRAPH_WIDTH - (self.frame_times.len() - index) as f32 * bar_width;
+            if let Some(bar) = tiny_skia::Rect::from_xywh(x, top + GRAPH_HEIGHT - height, bar_width, height) {
+                pixmap.fill_rect(bar, &paint_for_color(bar_color), tiny_skia::Transform::identity(), None);
+            }
+        }
+
+        let last_frame_ms = self.frame_times.back().map(duration_ms).unwrap_or(0.0);
+        let text = format!("{:.1} fps {:.1} ms", self.fps(), last_frame_ms);
+        self.draw_label(pixmap, &text, left + 4.0, top + 4.0, color::WHITE);
+    }
+
+    fn draw_label(&self, pixmap: &mut tiny_skia::Pixmap, text: &str, x: f32, y: f32, text_color: Color) {
+        let scale = rusttype::Scale::uniform(LABEL_HEIGHT);
+        let ascent = self.font.v_metrics(scale).ascent;
+        let glyphs: Vec<PositionedGlyph> = self.font.layout(text, scale, point(x + 2.0, y + ascent)).collect();
+        let text_width = glyphs
+            .last()
+            .map(|glyph| glyph.position().x + glyph.unpositioned().h_metrics().advance_width - x)
+            .unwrap_or(0.0);
+        if let Some(background) = tiny_skia::Rect::from_xywh(x, y, text_width + 2.0, LABEL_HEIGHT + 2.0) {
+            pixmap.fill_rect(background, &paint_for_color(color::BLACK.color_with_alpha(0xA0)), tiny_skia::Transform::identity(), None);
+        }
+
+        let width = pixmap.width() as i32;
+        let height = pixmap.height() as i32;
+        let (r, g, b, _) = text_color.rgba();
+        let pixels = pixmap.pixels_mut();
+        for glyph in &glyphs {
+            if let Some(bb) = glyph.pixel_bounding_box() {
+                glyph.draw(|gx, gy, coverage| {
+                    let px = gx as i32 + bb.min.x;
+                    let py = gy as i32 + bb.min.y;
+                    if px < 0 || py < 0 || px >= width || py >= height || coverage <= 0.0 {
+                        return;
+                    }
+                    let dst = &mut pixels[(py * width + px) as usize];
+                    let blended = blend_premultiplied(*dst, (r, g, b), coverage);
+                    *dst = blended;
+                });
+            }
+        }
+    }
+}
+
+fn blend_premultiplied(dst: tiny_skia::PremultipliedColorU8, (r, g, b): (u8, u8, u8), coverage: f32) -> tiny_skia::PremultipliedColorU8 {
+    let inverse = 1.0 - coverage;
+    let channel = |src: u8, dst: u8| -> u8 { (src as f32 * coverage + dst as f32 * inverse).round().min(255.0) as u8 };
+    let alpha = channel(255, dst.alpha());
+    let red = channel(r, dst.red()).min(alpha);
+    let green = channel(g, dst.green()).min(alpha);
+    let blue = channel(b, dst.blue()).min(alpha);
+    tiny_skia::PremultipliedColorU8::from_rgba(red, green, blue, alpha).unwrap_or(dst)
+}
+
+fn to_skia_rect(rect: &Rect) -> Option<tiny_skia::Rect> {
+    tiny_skia::Rect::from_xywh(
+        rect.pos.x as f32 + 0.5,
+        rect.pos.y as f32 + 0.5,
+        (rect.size.width - 1).max(1) as f32,
+        (rect.size.height - 1).max(1) as f32,
+    )
+}
+
+fn push_limited(history: &mut VecDeque<time::Duration>, value: time::Duration) {
+    if history.len() == FRAME_HISTORY {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
+
+fn duration_ms(duration: &time::Duration) -> f32 {
+    duration.as_secs() as f32 * 1000.0 + duration.subsec_nanos() as f32 / 1_000_000.0
+}
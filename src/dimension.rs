@@ -34,6 +34,12 @@ pub struct Scale {
     pub y: f32,
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct Skew {
+    pub x: f32,
+    pub y: f32,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Pos {
     pub x: i32,
@@ -87,3 +93,4 @@ pub const GRAVITY_TOP_CENTER:Gravity = Gravity {x : 0.5, y : 0.0};
 pub const GRAVITY_BOTTOM_CENTER:Gravity = Gravity {x : 0.5, y : 1.0};
 
 pub const SCALE_SINGLE: Scale = Scale {x : 1.0, y : 1.0};
+pub const SKEW_ZERO: Skew = Skew {x : 0.0, y : 0.0};
@@ -0,0 +1,142 @@
+use std::cmp;
+
+use color::Color;
+use dimension::*;
+use screen_writer::PIXEL_RGBA;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Shadow {
+    pub offset: Pos,
+    pub blur_radius: i32,
+    pub color: Color,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Glow {
+    pub radius: i32,
+    pub color: Color,
+}
+
+pub struct EffectBitmap {
+    pub raw_pixels: Vec<u32>,
+    pub frame: Rect,
+}
+
+impl Shadow {
+    pub fn new(offset: Pos, blur_radius: i32, color: Color) -> Shadow {
+        Shadow {
+            offset,
+            blur_radius,
+            color,
+        }
+    }
+
+    pub fn render(&self, raw_pixels: &[u32], frame: &Rect) -> EffectBitmap {
+        let radius = cmp::max(self.blur_radius, 0);
+        let (alpha, mut alpha_frame) = padded_alpha(raw_pixels, frame, radius);
+        let alpha = blur_alpha(alpha, &alpha_frame.size, radius);
+        alpha_frame.pos.x += self.offset.x;
+        alpha_frame.pos.y += self.offset.y;
+        EffectBitmap {
+            raw_pixels: colorize_alpha(&alpha, self.color, 1.0),
+            frame: alpha_frame,
+        }
+    }
+}
+
+impl Glow {
+    pub fn new(radius: i32, color: Color) -> Glow {
+        Glow { radius, color }
+    }
+
+    pub fn render(&self, raw_pixels: &[u32], frame: &Rect) -> EffectBitmap {
+        let radius = cmp::max(self.radius, 0);
+        let (alpha, alpha_frame) = padded_alpha(raw_pixels, frame, radius);
+        let alpha = blur_alpha(alpha, &alpha_frame.size, radius);
+        // Blurring spreads the coverage thin, boost it so the halo stays visible next to the edge.
+        EffectBitmap {
+            raw_pixels: colorize_alpha(&alpha, self.color, 2.0),
+            frame: alpha_frame,
+        }
+    }
+}
+
+fn padded_alpha(raw_pixels: &[u32], frame: &Rect, padding: i32) -> (Vec<u8>, Rect) {
+    let width = frame.size.width + padding * 2;
+    let height = frame.size.height + padding * 2;
+    let mut alpha = vec![0u8; (width * height) as usize];
+    for y in 0..frame.size.height {
+        for x in 0..frame.size.width {
+            let src_pixel = raw_pixels[(y * frame.size.width + x) as usize];
+            alpha[((y + padding) * width + x + padding) as usize] =
+                ((src_pixel >> PIXEL_RGBA.transp_offset) & 0xFF) as u8;
+        }
+    }
+    let alpha_frame = Rect {
+        pos: Pos {
+            x: frame.pos.x - padding,
+            y: frame.pos.y - padding,
+        },
+        size: Size { width, height },
+    };
+    (alpha, alpha_frame)
+}
+
+// Three box blur passes approximate a gaussian blur closely enough for shadows.
+fn blur_alpha(alpha: Vec<u8>, size: &Size, radius: i32) -> Vec<u8> {
+    if radius == 0 {
+        return alpha;
+    }
+    let box_radius = cmp::max(radius / 3, 1);
+    let mut alpha = alpha;
+    let mut buffer = vec![0u8; alpha.len()];
+    for _ in 0..3 {
+        box_blur_pass(&alpha, &mut buffer, size.width, size.height, box_radius, true);
+        box_blur_pass(&buffer, &mut alpha, size.width, size.height, box_radius, false);
+    }
+    alpha
+}
+
+fn box_blur_pass(src: &[u8], dst: &mut [u8], width: i32, height: i32, radius: i32, horizontal: bool) {
+    let (lines, length) = if horizontal { (height, width) } else { (width, height) };
+    let index = |line: i32, i: i32| -> usize {
+        if horizontal {
+            (line * width + i) as usize
+        } else {
+            (i * width + line) as usize
+        }
+    };
+    let window = (radius * 2 + 1) as u32;
+    for line in 0..lines {
+        let mut sum = 0u32;
+        for i in -radius..radius + 1 {
+            if i >= 0 && i < length {
+                sum += src[index(line, i)] as u32;
+            }
+        }
+        for i in 0..length {
+            dst[index(line, i)] = (sum / window) as u8;
+            let leaving = i - radius;
+            let entering = i + radius + 1;
+            if leaving >= 0 {
+                sum -= src[index(line, leaving)] as u32;
+            }
+            if entering < length {
+                sum += src[index(line, entering)] as u32;
+            }
+        }
+    }
+}
+
+fn colorize_alpha(alpha: &[u8], color: Color, intensity: f32) -> Vec<u32> {
+    let Color(c) = color;
+    let color_alpha = ((c >> PIXEL_RGBA.transp_offset) & 0xFF) as f32 / 255.0;
+    alpha
+        .iter()
+        .map(|&a| {
+            let a = (a as f32 * intensity * color_alpha).min(255.0) as u8;
+            let Color(pixel) = color.color_with_alpha(a);
+            pixel
+        })
+        .collect()
+}
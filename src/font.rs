@@ -0,0 +1,95 @@
+extern crate rusttype;
+extern crate rustybuzz;
+
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rusttype::{Font, FontCollection};
+
+use text::get_default_font_data;
+
+thread_local! {
+    static DEFAULT_FONT: TextFont = TextFont::from_bytes(get_default_font_data()).unwrap();
+}
+
+static NEXT_FONT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Fallback fonts shared by the text sprites of a scene.
+pub type SharedFontList = Rc<RefCell<Vec<TextFont>>>;
+
+/// Font for text layout, keeping the font data for shaping next to the parsed font used to
+/// rasterize glyphs. Clones share the data.
+#[derive(Clone)]
+pub struct TextFont {
+    id: usize,
+    data: Rc<Vec<u8>>,
+    font: Font<'static>,
+}
+
+impl TextFont {
+    pub fn from_bytes(data: Vec<u8>) -> Result<TextFont, String> {
+        let font = FontCollection::from_bytes(data.clone())
+            .and_then(|collection| collection.into_font())
+            .map_err(|e| format!("{:?}", e))?;
+        if rustybuzz::Face::from_slice(&data, 0).is_none() {
+            return Err(String::from("Unsupported font"));
+        }
+        Ok(TextFont {
+            id: NEXT_FONT_ID.fetch_add(1, Ordering::Relaxed),
+            data: Rc::new(data),
+            font,
+        })
+    }
+
+    pub fn from_file(file: &mut fs::File) -> Result<TextFont, String> {
+        use std::io::Read;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(|e| format!("{}", e))?;
+        TextFont::from_bytes(data)
+    }
+
+    pub fn font(&self) -> &Font<'static> {
+        &self.font
+    }
+
+    /// Identifies the font data, shared by clones.
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Position above the baseline and thickness of the underline, or of the strikethrough
+    /// line, in pixels at `scale`.
+    pub(crate) fn decoration_line(&self, strikethrough: bool, scale: rusttype::Scale) -> (f32, f32) {
+        let face = self.face();
+        let em = face.units_per_em() as f32;
+        let metrics = if strikethrough { face.strikeout_metrics() } else { face.underline_metrics() };
+        let (position, thickness) = match metrics {
+            Some(metrics) => (metrics.position as f32, metrics.thickness as f32),
+            None if strikethrough => (em * 0.25, em * 0.05),
+            None => (em * -0.1, em * 0.05),
+        };
+        let v_metrics = self.font.v_metrics_unscaled();
+        let pixels_per_unit = scale.y / (v_metrics.ascent - v_metrics.descent);
+        (position * pixels_per_unit, thickness * pixels_per_unit)
+    }
+
+    pub(crate) fn face(&self) -> rustybuzz::Face<'_> {
+        // The data was checked to parse when the font was made.
+        rustybuzz::Face::from_slice(&self.data, 0).unwrap()
+    }
+}
+
+impl PartialEq for TextFont {
+    fn eq(&self, other: &TextFont) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Default for TextFont {
+    /// The font embedded in the library.
+    fn default() -> TextFont {
+        DEFAULT_FONT.with(|font| font.clone())
+    }
+}
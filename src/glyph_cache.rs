@@ -0,0 +1,250 @@
+extern crate rusttype;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use rusttype::{point, GlyphId};
+
+use dimension::*;
+use font::TextFont;
+
+pub const DEFAULT_GLYPH_CACHE_BUDGET: usize = 4 * 1024 * 1024;
+
+/// Subpixel positions a glyph is rasterized at along each axis.
+const SUBPIXEL_STEPS: f32 = 4.0;
+
+/// Horizontal shift per pixel above the baseline of synthetic italic glyphs.
+const ITALIC_SLANT: f32 = 0.2;
+
+pub type SharedGlyphCache = Rc<RefCell<GlyphCache>>;
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+struct GlyphKey {
+    font: usize,
+    id: u16,
+    scale_x: u32,
+    scale_y: u32,
+    offset_x: u8,
+    offset_y: u8,
+    bold: bool,
+    italic: bool,
+}
+
+struct CacheEntry {
+    bitmap: Rc<GlyphBitmap>,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// Coverage of a rasterized glyph, one byte per pixel.
+pub struct GlyphBitmap {
+    /// Top left of the coverage from the pixel the glyph is positioned at.
+    pub offset: Pos,
+    pub size: Size,
+    pub coverage: Vec<u8>,
+}
+
+/// Scene wide cache of rasterized glyphs, keyed by font, glyph, size and subpixel position.
+/// Least recently used glyphs are evicted once the memory budget is exceeded.
+pub struct GlyphCache {
+    entries: HashMap<GlyphKey, CacheEntry>,
+    budget: usize,
+    used: usize,
+    tick: u64,
+}
+
+impl GlyphCache {
+    pub fn new(budget: usize) -> GlyphCache {
+        GlyphCache {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+            tick: 0,
+        }
+    }
+
+    pub fn new_shared(budget: usize) -> SharedGlyphCache {
+        Rc::new(RefCell::new(GlyphCache::new(budget)))
+    }
+
+    /// Bitmap of glyph `id` of `font` at `position`, rounded to a quarter pixel, with the pixel
+    /// the bitmap offset is relative to. Bold and italic glyphs are synthesized by widening and
+    /// slanting the regular glyph.
+    pub fn glyph(
+        &mut self,
+        font: &TextFont,
+        id: u16,
+        scale: rusttype::Scale,
+        position: rusttype::Point<f32>,
+        bold: bool,
+        italic: bool,
+    ) -> (Rc<GlyphBitmap>, Pos) {
+        let (x, offset_x) = split_position(position.x);
+        let (y, offset_y) = split_position(position.y);
+        let key = GlyphKey {
+            font: font.id(),
+            id,
+            scale_x: scale.x.to_bits(),
+            scale_y: scale.y.to_bits(),
+            offset_x,
+            offset_y,
+            bold,
+            italic,
+        };
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.last_used = tick;
+            return (entry.bitmap.clone(), Pos { x, y });
+        }
+        let mut bitmap = rasterize(font, id, scale, offset_x, offset_y);
+        if bold {
+            bitmap = embolden(bitmap, synthetic_bold_width(scale));
+        }
+        if italic {
+            bitmap = slant(bitmap, ITALIC_SLANT);
+        }
+        let bitmap = Rc::new(bitmap);
+        let bytes = mem::size_of::<GlyphKey>() + mem::size_of::<CacheEntry>() + bitmap.coverage.len();
+        self.entries.insert(
+            key,
+            CacheEntry {
+                bitmap: bitmap.clone(),
+                bytes,
+                last_used: tick,
+            },
+        );
+        self.used += bytes;
+        self.evict();
+        (bitmap, Pos { x, y })
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    /// Bytes currently held by the cache.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used = 0;
+    }
+
+    fn evict(&mut self) {
+        if self.used <= self.budget {
+            return;
+        }
+        // Glyphs are small, evict down to three quarters of the budget at once rather than
+        // searching for the oldest glyph on every insert.
+        let target = self.budget / 4 * 3;
+        let mut keys: Vec<(u64, GlyphKey)> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect();
+        keys.sort_by_key(|&(last_used, _)| last_used);
+        for (_, key) in keys {
+            if self.used <= target {
+                break;
+            }
+            if let Some(entry) = self.entries.remove(&key) {
+                self.used -= entry.bytes;
+            }
+        }
+    }
+}
+
+/// Width synthetic bold glyphs are widened by, added to their advance.
+pub(crate) fn synthetic_bold_width(scale: rusttype::Scale) -> f32 {
+    scale.x / 24.0
+}
+
+/// Whole pixel and subpixel step of a coordinate.
+fn split_position(value: f32) -> (i32, u8) {
+    let steps = (value * SUBPIXEL_STEPS).round();
+    let pixel = (steps / SUBPIXEL_STEPS).floor();
+    (pixel as i32, (steps - pixel * SUBPIXEL_STEPS) as u8)
+}
+
+fn rasterize(font: &TextFont, id: u16, scale: rusttype::Scale, offset_x: u8, offset_y: u8) -> GlyphBitmap {
+    let position = point(offset_x as f32 / SUBPIXEL_STEPS, offset_y as f32 / SUBPIXEL_STEPS);
+    let glyph = font.font().glyph(GlyphId(id as u32)).scaled(scale).positioned(position);
+    match glyph.pixel_bounding_box() {
+        Some(bb) => {
+            let size = Size { width: bb.width(), height: bb.height() };
+            let mut coverage = vec![0; (size.width * size.height) as usize];
+            glyph.draw(|x, y, v| {
+                coverage[(y as i32 * size.width + x as i32) as usize] = (v * 255.0) as u8;
+            });
+            GlyphBitmap {
+                offset: Pos { x: bb.min.x, y: bb.min.y },
+                size,
+                coverage,
+            }
+        }
+        None => GlyphBitmap {
+            offset: POS_ZERO,
+            size: SIZE_ZERO,
+            coverage: Vec::new(),
+        },
+    }
+}
+
+/// Smears the coverage `width` pixels to the right.
+fn embolden(bitmap: GlyphBitmap, width: f32) -> GlyphBitmap {
+    if bitmap.coverage.is_empty() || width <= 0.0 {
+        return bitmap;
+    }
+    let extra = width.ceil() as i32;
+    let size = Size { width: bitmap.size.width + extra, height: bitmap.size.height };
+    let mut coverage = vec![0u8; (size.width * size.height) as usize];
+    for y in 0..size.height {
+        for x in 0..size.width {
+            let mut value = 0.0_f32;
+            for shift in 0..extra + 1 {
+                let src_x = x - shift;
+                if src_x >= 0 && src_x < bitmap.size.width {
+                    let weight = (width - shift as f32 + 1.0).min(1.0);
+                    value = value.max(bitmap.coverage[(y * bitmap.size.width + src_x) as usize] as f32 * weight);
+                }
+            }
+            coverage[(y * size.width + x) as usize] = value as u8;
+        }
+    }
+    GlyphBitmap { offset: bitmap.offset, size, coverage }
+}
+
+/// Shears the coverage to the right above the baseline and to the left below it.
+fn slant(bitmap: GlyphBitmap, slant: f32) -> GlyphBitmap {
+    if bitmap.coverage.is_empty() {
+        return bitmap;
+    }
+    // Shift of each row, from the center of the row to the baseline.
+    let shift = |row: i32| -((bitmap.offset.y + row) as f32 + 0.5) * slant;
+    let min_shift = shift(bitmap.size.height - 1).floor() as i32;
+    let max_shift = shift(0).ceil() as i32;
+    let size = Size { width: bitmap.size.width + max_shift - min_shift + 1, height: bitmap.size.height };
+    let mut coverage = vec![0u8; (size.width * size.height) as usize];
+    for y in 0..size.height {
+        let row_shift = shift(y) - min_shift as f32;
+        let whole = row_shift.floor() as i32;
+        let fraction = row_shift - whole as f32;
+        for x in 0..bitmap.size.width {
+            let value = bitmap.coverage[(y * bitmap.size.width + x) as usize] as f32;
+            let dst = (y * size.width + x + whole) as usize;
+            coverage[dst] = (coverage[dst] as f32 + value * (1.0 - fraction)).min(255.0) as u8;
+            coverage[dst + 1] = (coverage[dst + 1] as f32 + value * fraction).min(255.0) as u8;
+        }
+    }
+    GlyphBitmap {
+        offset: Pos { x: bitmap.offset.x + min_shift, y: bitmap.offset.y },
+        size,
+        coverage,
+    }
+}
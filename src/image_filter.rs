@@ -0,0 +1,129 @@
+extern crate image;
+
+use image::{imageops, RgbaImage};
+
+use color::Color;
+use dimension::*;
+use sprite::pixels_from_rgba;
+
+/// Color filter applied to texture pixels once they are scaled. Amounts follow CSS filters:
+/// 0 leaves the image unchanged for grayscale, sepia and invert, 1 does so for brightness,
+/// contrast and saturation.
+#[derive(Debug, Copy, Clone)]
+pub enum ImageFilter {
+    /// Multiplies the colors by the color, its alpha is the strength.
+    Tint(Color),
+    Grayscale(f32),
+    Sepia(f32),
+    Brightness(f32),
+    Contrast(f32),
+    Saturation(f32),
+    Invert(f32),
+    /// Gaussian blur with the radius in pixels.
+    Blur(f32),
+}
+
+/// Applies `filters` in order to straight alpha `pixels` of `size`.
+pub fn apply_filters(pixels: &mut Vec<u32>, size: &Size, filters: &[ImageFilter]) {
+    for filter in filters {
+        match *filter {
+            ImageFilter::Blur(radius) => blur(pixels, size, radius),
+            _ => {
+                for pixel in pixels.iter_mut() {
+                    *pixel = filter_pixel(*pixel, filter);
+                }
+            }
+        }
+    }
+}
+
+/// Key telling apart pixels filtered differently, for caching.
+pub fn filters_key(filters: &[ImageFilter]) -> String {
+    format!("{:?}", filters)
+}
+
+fn filter_pixel(pixel: u32, filter: &ImageFilter) -> u32 {
+    let alpha = pixel >> 24;
+    if alpha == 0 {
+        return pixel;
+    }
+    let rgb = [
+        (pixel & 0xFF) as f32,
+        ((pixel >> 8) & 0xFF) as f32,
+        ((pixel >> 16) & 0xFF) as f32,
+    ];
+    let [r, g, b] = rgb;
+    let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let filtered = match *filter {
+        ImageFilter::Tint(color) => {
+            let (tint_r, tint_g, tint_b, tint_a) = color.rgba();
+            let strength = tint_a as f32 / 255.0;
+            [
+                mix(r, r * tint_r as f32 / 255.0, strength),
+                mix(g, g * tint_g as f32 / 255.0, strength),
+                mix(b, b * tint_b as f32 / 255.0, strength),
+            ]
+        }
+        ImageFilter::Grayscale(amount) => [
+            mix(r, luminance, amount),
+            mix(g, luminance, amount),
+            mix(b, luminance, amount),
+        ],
+        ImageFilter::Sepia(amount) => [
+            mix(r, 0.393 * r + 0.769 * g + 0.189 * b, amount),
+            mix(g, 0.349 * r + 0.686 * g + 0.168 * b, amount),
+            mix(b, 0.272 * r + 0.534 * g + 0.131 * b, amount),
+        ],
+        ImageFilter::Brightness(amount) => [r * amount, g * amount, b * amount],
+        ImageFilter::Contrast(amount) => [
+            (r - 128.0) * amount + 128.0,
+            (g - 128.0) * amount + 128.0,
+            (b - 128.0) * amount + 128.0,
+        ],
+        ImageFilter::Saturation(amount) => [
+            mix(luminance, r, amount),
+            mix(luminance, g, amount),
+            mix(luminance, b, amount),
+        ],
+        ImageFilter::Invert(amount) => [
+            mix(r, 255.0 - r, amount),
+            mix(g, 255.0 - g, amount),
+            mix(b, 255.0 - b, amount),
+        ],
+        ImageFilter::Blur(_) => rgb,
+    };
+    let channel = |value: f32| value.round().clamp(0.0, 255.0) as u32;
+    channel(filtered[0]) | (channel(filtered[1]) << 8) | (channel(filtered[2]) << 16) | (alpha << 24)
+}
+
+fn mix(from: f32, to: f32, amount: f32) -> f32 {
+    from + (to - from) * amount
+}
+
+/// Blurs premultiplied colors, so transparent pixels do not darken the edges.
+fn blur(pixels: &mut Vec<u32>, size: &Size, radius: f32) {
+    if radius <= 0.0 || pixels.is_empty() {
+        return;
+    }
+    let mut premultiplied = Vec::with_capacity(pixels.len() * 4);
+    for pixel in pixels.iter() {
+        let alpha = pixel >> 24;
+        for shift in &[0, 8, 16] {
+            premultiplied.push((((pixel >> shift) & 0xFF) * alpha / 255) as u8);
+        }
+        premultiplied.push(alpha as u8);
+    }
+    let image = match RgbaImage::from_raw(size.width as u32, size.height as u32, premultiplied) {
+        Some(image) => image,
+        None => return,
+    };
+    // A gaussian covers its radius at about three sigma.
+    let mut raw = imageops::blur(&image, radius / 3.0).into_raw();
+    for rgba in raw.chunks_mut(4) {
+        let alpha = rgba[3] as u32;
+        for channel in rgba.iter_mut().take(3) {
+            *channel = (*channel as u32 * 255).checked_div(alpha).unwrap_or(0).min(255) as u8;
+        }
+    }
+    *pixels = pixels_from_rgba(&raw);
+}
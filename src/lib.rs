@@ -1,34 +1,81 @@
+extern crate chrono;
+extern crate gif;
 extern crate image;
 extern crate libc;
 extern crate memmap;
+extern crate png;
+extern crate qrcode;
+extern crate rayon;
+extern crate resvg;
+extern crate serde_json;
 extern crate rusttype;
+extern crate rustybuzz;
+extern crate tiny_skia;
+extern crate unicode_bidi;
+extern crate unicode_linebreak;
 extern crate xml;
 
 //pub use scene::{Scene};
 //pub use scene_xml::*;
 pub use node::Node;
+pub use barcode::{BarcodeSprite, ErrorCorrection, QrCodeSprite, Symbology};
+pub use canvas::Canvas;
+pub use chart::{ChartKind, ChartSamples, ChartSprite};
+pub use canvas_sprite::{CanvasInvalidator, CanvasSprite, Painter};
+pub use mask::{Mask, MaskShape};
+pub use nine_patch::{Insets, NinePatchSprite, INSETS_ZERO};
 pub use color::Color;
-pub use shape::RectSprite;
-pub use text::TextSprite;
-pub use texture::TextureSprite;
+pub use shape::{LineCap, LineJoin, RectSprite, Shape, ShapeSprite, Stroke};
+pub use sprite_sheet::{PlaybackMode, SpriteSheetSprite};
+pub use clock::{Clock, TimeZone};
+pub use font::{SharedFontList, TextFont};
+pub use glyph_cache::{GlyphCache, SharedGlyphCache};
+pub use rich_text::{parse_markup, TextSpan, TextStyle};
+pub use text::{TextAlign, TextEllipsis, TextOutline, TextSprite};
+pub use image_filter::ImageFilter;
+pub use texture::{Fit, TextureSprite};
+pub use texture_cache::{SharedTextureCache, TextureCache};
 pub use dimension::*;
+pub use effect::{Glow, Shadow};
+pub use transform::{Transform, TRANSFORM_IDENTITY};
 pub use scene::Scene;
+pub use sprite::{AsAny, Sprite};
 pub use scene::SceneState;
 pub use screen_writer::{screen_writer_for_framebuffer, screen_writer_for_png, set_graphics_mode,
-                        set_text_mode, ScreenWriter};
+                        set_text_mode, PixelDef, ScreenInfo, ScreenWriter};
 
+mod animation;
+mod barcode;
 mod c;
+mod canvas;
+mod canvas_sprite;
+mod chart;
+mod clock;
 mod color;
+mod compositor;
+mod debug_overlay;
 mod dimension;
+mod effect;
+mod font;
+mod glyph_cache;
+mod image_filter;
+mod mask;
 mod screen_writer;
+mod nine_patch;
 mod node;
+mod path;
 mod resource;
+mod rich_text;
 pub mod scene;
 pub mod scene_bundle;
 mod shape;
 mod sprite;
+mod sprite_sheet;
 mod text;
+mod text_layout;
 mod texture;
+mod texture_cache;
+mod transform;
 
 pub mod version {
     include!(concat!(env!("OUT_DIR"), "/version.rs"));
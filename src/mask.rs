@@ -0,0 +1,156 @@
+extern crate image;
+extern crate tiny_skia;
+
+use image::{imageops, RgbaImage};
+
+use dimension::*;
+use transform::Transform;
+
+/// Magic constant to approximate a quarter circle with a cubic curve.
+const KAPPA: f32 = 0.552_284_8;
+
+pub enum MaskShape {
+    /// Rounded rectangle with the corner radius in pixels.
+    RoundedRect(f32),
+    Ellipse,
+    /// Alpha channel of the image, stretched to the node frame.
+    Image(RgbaImage),
+}
+
+/// Non-rectangular clip of a node and its subtree. Coverage is rendered at the node frame size
+/// whenever the node is drawn.
+pub struct Mask {
+    pub shape: MaskShape,
+    alpha: Vec<u8>,
+    size: Size,
+}
+
+/// Mask of an ancestor (or the node itself) carried by render items.
+#[derive(Debug, Copy, Clone)]
+pub struct MaskClip {
+    alpha_ptr: *const u8,
+    frame: Rect,
+    /// Maps screen points back to the layout of the masking node, None without a transform.
+    inverse: Option<Transform>,
+}
+
+impl Mask {
+    pub fn new(shape: MaskShape) -> Mask {
+        Mask {
+            shape,
+            alpha: Vec::new(),
+            size: Size { width: 0, height: 0 },
+        }
+    }
+
+    pub fn new_from_image_filename(filename: &str) -> Result<Mask, String> {
+        match image::open(filename) {
+            Ok(image) => Ok(Mask::new(MaskShape::Image(image.to_rgba()))),
+            Err(e) => Err(format!("{} {}", e, filename)),
+        }
+    }
+
+    pub fn draw(&mut self, size: &Size) {
+        self.size = *size;
+        self.alpha.clear();
+        if size.width <= 0 || size.height <= 0 {
+            return;
+        }
+        match self.shape {
+            MaskShape::Image(ref image) => {
+                let image = imageops::resize(image, size.width as u32, size.height as u32, imageops::Triangle);
+                self.alpha = image.into_raw().chunks(4).map(|rgba| rgba[3]).collect();
+            }
+            MaskShape::RoundedRect(radius) => {
+                self.alpha = fill_path(size, rounded_rect_path(size, radius));
+            }
+            MaskShape::Ellipse => {
+                let path = tiny_skia::Rect::from_xywh(0.0, 0.0, size.width as f32, size.height as f32)
+                    .and_then(tiny_skia::PathBuilder::from_oval);
+                self.alpha = fill_path(size, path);
+            }
+        }
+    }
+
+    pub fn clip(&self, frame: &Rect, world_transform: &Transform) -> MaskClip {
+        let inverse = if world_transform.is_identity() {
+            None
+        } else {
+            world_transform.invert()
+        };
+        // A mask not drawn at the frame size yet hides everything.
+        let valid = self.size.width == frame.size.width && self.size.height == frame.size.height && !self.alpha.is_empty();
+        MaskClip {
+            alpha_ptr: self.alpha.as_ptr(),
+            frame: if valid { *frame } else { RECT_ZERO },
+            inverse,
+        }
+    }
+}
+
+impl MaskClip {
+    /// Mask coverage (0...255) of the screen pixel.
+    fn coverage(&self, x: i32, y: i32) -> u32 {
+        let (layout_x, layout_y) = match self.inverse {
+            Some(ref inverse) => {
+                let (layout_x, layout_y) = inverse.apply(x as f32 + 0.5, y as f32 + 0.5);
+                (layout_x.floor() as i32, layout_y.floor() as i32)
+            }
+            None => (x, y),
+        };
+        let mask_x = layout_x - self.frame.pos.x;
+        let mask_y = layout_y - self.frame.pos.y;
+        if mask_x < 0 || mask_y < 0 || mask_x >= self.frame.size.width || mask_y >= self.frame.size.height {
+            return 0;
+        }
+        unsafe { *self.alpha_ptr.offset((mask_y * self.frame.size.width + mask_x) as isize) as u32 }
+    }
+}
+
+/// Combined coverage (0...255) of all `masks` at the screen pixel.
+pub fn coverage(masks: &[MaskClip], x: i32, y: i32) -> u32 {
+    let mut coverage = 255;
+    for mask in masks {
+        coverage = coverage * mask.coverage(x, y) / 255;
+        if coverage == 0 {
+            break;
+        }
+    }
+    coverage
+}
+
+/// Scales the alpha of an RGBA pixel by `coverage`.
+pub fn apply_coverage(pixel: u32, coverage: u32) -> u32 {
+    let alpha = (pixel >> 24) * coverage / 255;
+    (pixel & 0x00FF_FFFF) | (alpha << 24)
+}
+
+fn rounded_rect_path(size: &Size, radius: f32) -> Option<tiny_skia::Path> {
+    let width = size.width as f32;
+    let height = size.height as f32;
+    let radius = radius.max(0.0).min(width / 2.0).min(height / 2.0);
+    let handle = radius * (1.0 - KAPPA);
+    let mut builder = tiny_skia::PathBuilder::new();
+    builder.move_to(radius, 0.0);
+    builder.line_to(width - radius, 0.0);
+    builder.cubic_to(width - handle, 0.0, width, handle, width, radius);
+    builder.line_to(width, height - radius);
+    builder.cubic_to(width, height - handle, width - handle, height, width - radius, height);
+    builder.line_to(radius, height);
+    builder.cubic_to(handle, height, 0.0, height - handle, 0.0, height - radius);
+    builder.line_to(0.0, radius);
+    builder.cubic_to(0.0, handle, handle, 0.0, radius, 0.0);
+    builder.close();
+    builder.finish()
+}
+
+fn fill_path(size: &Size, path: Option<tiny_skia::Path>) -> Vec<u8> {
+    let mut mask = match tiny_skia::Mask::new(size.width as u32, size.height as u32) {
+        Some(mask) => mask,
+        None => return Vec::new(),
+    };
+    if let Some(path) = path {
+        mask.fill_path(&path, tiny_skia::FillRule::Winding, true, tiny_skia::Transform::identity());
+    }
+    mask.data().to_vec()
+}
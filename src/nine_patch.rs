@@ -0,0 +1,175 @@
+extern crate image;
+
+use std::cmp;
+
+use image::{imageops, DynamicImage, GenericImage, RgbaImage};
+
+use dimension::*;
+use screen_writer::ScreenInfo;
+use sprite::{pixels_from_rgba, Sprite};
+
+/// Fixed margins of a nine-patch image in source pixels.
+#[derive(Debug, Copy, Clone)]
+pub struct Insets {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+pub const INSETS_ZERO: Insets = Insets {
+    left: 0,
+    top: 0,
+    right: 0,
+    bottom: 0,
+};
+
+/// Image stretched to the node frame keeping its corners intact. Corners are copied as is,
+/// edges are stretched along one axis and the center along both.
+pub struct NinePatchSprite {
+    pub insets: Insets,
+    pub filter: imageops::FilterType,
+    image: Option<RgbaImage>,
+    raw_pixels: Vec<u32>,
+    frame: Rect,
+}
+
+impl NinePatchSprite {
+    pub fn new() -> NinePatchSprite {
+        NinePatchSprite {
+            insets: INSETS_ZERO,
+            filter: imageops::Triangle,
+            image: None,
+            raw_pixels: Vec::new(),
+            frame: RECT_ZERO,
+        }
+    }
+
+    /// Loads the image. Android style `.9.png` files have their insets read from the black
+    /// markers in the one pixel border, which is stripped from the image.
+    pub fn set_image_filename(&mut self, filename: &str) {
+        match image::open(filename) {
+            Ok(image) => {
+                if filename.to_lowercase().ends_with(".9.png") {
+                    let (image, insets) = NinePatchSprite::split_markers(image.to_rgba());
+                    self.image = Some(image);
+                    self.insets = insets;
+                } else {
+                    self.image = Some(image.to_rgba());
+                }
+            }
+            Err(e) => println!("{} {}", e, filename),
+        }
+    }
+
+    pub fn set_image(&mut self, image: &DynamicImage) {
+        self.image = Some(image.to_rgba());
+    }
+
+    fn split_markers(image: RgbaImage) -> (RgbaImage, Insets) {
+        let width = image.width();
+        let height = image.height();
+        if width < 3 || height < 3 {
+            return (image, INSETS_ZERO);
+        }
+        let is_marker = |x: u32, y: u32| -> bool {
+            let pixel = image.get_pixel(x, y).data;
+            pixel[3] == 0xFF && pixel[0] == 0 && pixel[1] == 0 && pixel[2] == 0
+        };
+
+        let horizontal: Vec<u32> = (1..width - 1).filter(|&x| is_marker(x, 0)).collect();
+        let vertical: Vec<u32> = (1..height - 1).filter(|&y| is_marker(0, y)).collect();
+        let content_width = (width - 2) as i32;
+        let content_height = (height - 2) as i32;
+
+        let insets = Insets {
+            left: horizontal.first().map(|&x| x as i32 - 1).unwrap_or(0),
+            top: vertical.first().map(|&y| y as i32 - 1).unwrap_or(0),
+            right: horizontal.last().map(|&x| content_width - x as i32).unwrap_or(0),
+            bottom: vertical.last().map(|&y| content_height - y as i32).unwrap_or(0),
+        };
+        let mut image = image;
+        let content = imageops::crop(&mut image, 1, 1, width - 2, height - 2).to_image();
+        (content, insets)
+    }
+}
+
+impl Default for NinePatchSprite {
+    fn default() -> NinePatchSprite {
+        NinePatchSprite::new()
+    }
+}
+
+impl<'a> Sprite<'a> for NinePatchSprite {
+    fn draw(&mut self, outer_rect: &Rect, _screen_info: &ScreenInfo) {
+        self.frame = Rect {
+            pos: POS_ZERO,
+            size: outer_rect.size,
+        };
+        let width = cmp::max(outer_rect.size.width, 0);
+        let height = cmp::max(outer_rect.size.height, 0);
+        let mut target = RgbaImage::new(width as u32, height as u32);
+
+        if let Some(ref image) = self.image {
+            let source_width = image.width() as i32;
+            let source_height = image.height() as i32;
+            let left = cmp::min(self.insets.left, source_width);
+            let right = cmp::min(self.insets.right, source_width - left);
+            let top = cmp::min(self.insets.top, source_height);
+            let bottom = cmp::min(self.insets.bottom, source_height - top);
+
+            // Margins are shrunk proportionally when the frame is smaller than the corners.
+            let horizontal_scale = if left + right > width { width as f32 / (left + right) as f32 } else { 1.0 };
+            let vertical_scale = if top + bottom > height { height as f32 / (top + bottom) as f32 } else { 1.0 };
+            let target_left = (left as f32 * horizontal_scale) as i32;
+            let target_right = (right as f32 * horizontal_scale) as i32;
+            let target_top = (top as f32 * vertical_scale) as i32;
+            let target_bottom = (bottom as f32 * vertical_scale) as i32;
+
+            let source_columns = [(0, left), (left, source_width - left - right), (source_width - right, right)];
+            let source_rows = [(0, top), (top, source_height - top - bottom), (source_height - bottom, bottom)];
+            let target_columns = [
+                (0, target_left),
+                (target_left, width - target_left - target_right),
+                (width - target_right, target_right),
+            ];
+            let target_rows = [
+                (0, target_top),
+                (target_top, height - target_top - target_bottom),
+                (height - target_bottom, target_bottom),
+            ];
+
+            let mut source = image.clone();
+            for row in 0..3 {
+                for column in 0..3 {
+                    let (source_x, source_width) = source_columns[column];
+                    let (source_y, source_height) = source_rows[row];
+                    let (target_x, target_width) = target_columns[column];
+                    let (target_y, target_height) = target_rows[row];
+                    if source_width <= 0 || source_height <= 0 || target_width <= 0 || target_height <= 0 {
+                        continue;
+                    }
+                    let patch = imageops::crop(
+                        &mut source,
+                        source_x as u32,
+                        source_y as u32,
+                        source_width as u32,
+                        source_height as u32,
+                    ).to_image();
+                    let patch = if source_width == target_width && source_height == target_height {
+                        patch
+                    } else {
+                        imageops::resize(&patch, target_width as u32, target_height as u32, self.filter)
+                    };
+                    target.copy_from(&patch, target_x as u32, target_y as u32);
+                }
+            }
+        }
+
+        self.raw_pixels = pixels_from_rgba(&target.into_raw());
+    }
+
+    fn pixels(&self) -> (&[u32], &Rect) {
+        (&self.raw_pixels, &self.frame)
+    }
+}
@@ -1,13 +1,22 @@
 extern crate uuid;
 
 use std::ptr;
+use std::time;
 
 use screen_writer::{ScreenInfo};
 use text::*;
 use texture::*;
 use dimension::*;
 use shape::*;
+use nine_patch::NinePatchSprite;
+use sprite_sheet::SpriteSheetSprite;
+use canvas_sprite::CanvasSprite;
+use barcode::{BarcodeSprite, QrCodeSprite};
+use chart::ChartSprite;
 use sprite::*;
+use effect::{EffectBitmap, Glow, Shadow};
+use mask::{Mask, MaskClip};
+use transform::{Transform, TRANSFORM_IDENTITY};
 
 pub struct Node<'a> {
     pub visible : bool,
@@ -17,8 +26,20 @@ pub struct Node<'a> {
     pub anchor_point:AnchorPoint,
     pub frame : Rect,
     pub need_draw: bool,
-    sprite: Box<Sprite<'a>>,
-    pub clip_to_bounds : bool
+    sprite: Box<dyn Sprite<'a> + 'static>,
+    pub clip_to_bounds : bool,
+    pub shadow : Option<Shadow>,
+    pub glow : Option<Glow>,
+    effects : Vec<EffectBitmap>,
+    /// Clips the node and its subtree to the mask shape.
+    pub mask : Option<Mask>,
+    /// Rotation in degrees around `anchor_point`.
+    pub rotation : f32,
+    pub scale : Scale,
+    /// Skew angles in degrees around `anchor_point`.
+    pub skew : Skew,
+    pub world_transform : Transform,
+    parent_transform : Transform,
 }
 
 pub type NodeKey = [u8;16];
@@ -60,19 +81,138 @@ impl<'a, 'b : 'a> Node<'a> {
         }
     }
 
-    pub fn layout(&mut self, frame: Rect, _screen_info:&ScreenInfo) {
+    pub fn layout(&mut self, frame: Rect, parent_transform:&Transform, _screen_info:&ScreenInfo) {
         self.frame = frame;
+        self.update_transform(parent_transform);
     }
 
-    pub fn draw_if_need(&mut self, screen_info:&ScreenInfo) {
+    /// Recomputes the world transform from the parent transform, and `rotation`, `scale` and
+    /// `skew` of the node. Returns whether it changed.
+    pub fn update_transform(&mut self, parent_transform:&Transform) -> bool {
+        let world_transform = parent_transform.multiply(&self.local_transform());
+        if world_transform == self.world_transform && *parent_transform == self.parent_transform {
+            return false;
+        }
+        self.parent_transform = *parent_transform;
+        self.world_transform = world_transform;
+        true
+    }
+
+    /// Rotation, scale and skew of the node around its anchor point, in layout coordinates.
+    pub fn local_transform(&self) -> Transform {
+        if self.rotation == 0.0 && self.scale.x == 1.0 && self.scale.y == 1.0 && self.skew.x == 0.0 && self.skew.y == 0.0 {
+            return TRANSFORM_IDENTITY;
+        }
+        let pivot_x = self.frame.pos.x as f32 + self.frame.size.width as f32 * self.anchor_point.x;
+        let pivot_y = self.frame.pos.y as f32 + self.frame.size.height as f32 * self.anchor_point.y;
+
+        Transform::translation(pivot_x, pivot_y)
+            .multiply(&Transform::rotation(self.rotation))
+            .multiply(&Transform::skew(&self.skew))
+            .multiply(&Transform::scale(&self.scale))
+            .multiply(&Transform::translation(-pivot_x, -pivot_y))
+    }
+
+    /// Draws the sprite if needed, returns whether it was drawn.
+    pub fn draw_if_need(&mut self, screen_info:&ScreenInfo) -> bool {
         if self.need_draw {
             self.sprite.draw(&self.frame, screen_info);
+            let (raw_pixels, sprite_frame) = self.sprite.pixels();
+            let frame_pixels = (sprite_frame.size.width.max(0) * sprite_frame.size.height.max(0)) as usize;
+            if raw_pixels.len() < frame_pixels {
+                println!("Sprite of node {} has {} pixels for a frame of {}, skipped", self.tag, raw_pixels.len(), frame_pixels);
+            }
+            self.draw_effects();
+            if let Some(ref mut mask) = self.mask {
+                mask.draw(&self.frame.size);
+            }
             self.need_draw = false;
+            return true;
         }
+        false
     }
 
-    pub fn render(&mut self, parent_node_frame:&Rect, screen_info:&ScreenInfo, canvas_ptr:*mut u32) {
-        self.sprite.render(parent_node_frame,&self.frame, self.clip_to_bounds, screen_info, canvas_ptr);
+    /// Advances the sprite to the scene `clock` and marks the node for drawing when it changed.
+    pub fn update(&mut self, clock:time::Duration) -> bool {
+        if self.sprite.update(clock) {
+            self.need_draw = true;
+            return true;
+        }
+        false
+    }
+
+    pub fn sprite_type(&self) -> &'static str {
+        self.sprite.type_name()
+    }
+
+    /// Screen area covered by the node frame once its transform is applied.
+    pub fn bounds(&self) -> Rect {
+        if self.world_transform.is_identity() {
+            self.frame
+        } else {
+            self.world_transform.bounding_rect(&self.frame)
+        }
+    }
+
+    fn draw_effects(&mut self) {
+        self.effects.clear();
+        if self.shadow.is_none() && self.glow.is_none() {
+            return;
+        }
+        let (raw_pixels, sprite_frame) = self.sprite.pixels();
+        if raw_pixels.len() < (sprite_frame.size.width.max(0) * sprite_frame.size.height.max(0)) as usize {
+            return;
+        }
+        if let Some(ref shadow) = self.shadow {
+            self.effects.push(shadow.render(raw_pixels, sprite_frame));
+        }
+        if let Some(ref glow) = self.glow {
+            self.effects.push(glow.render(raw_pixels, sprite_frame));
+        }
+    }
+
+    /// Clip the node applies to itself and its subtree.
+    pub fn mask_clip(&self) -> Option<MaskClip> {
+        self.mask.as_ref().map(|mask| mask.clip(&self.frame, &self.world_transform))
+    }
+
+    /// Appends what the node puts on the canvas, effects first, to the scene display list.
+    /// `masks` are the clips of the ancestors, effects are only clipped by them.
+    pub fn render(&self, parent_node_frame:&Rect, masks:&[MaskClip], items:&mut Vec<RenderItem>) {
+        for effect in &self.effects {
+            // Effects spread outside of the node, so they are framed by their own bounds and only clipped by the parent.
+            let effect_outer_frame = Rect {
+                pos : Pos {
+                    x : self.frame.pos.x + effect.frame.pos.x,
+                    y : self.frame.pos.y + effect.frame.pos.y,
+                },
+                size : effect.frame.size,
+            };
+            let effect_inner_frame = Rect { pos : POS_ZERO, size : effect.frame.size };
+            items.push(self.render_item(effect.raw_pixels.as_ptr(), parent_node_frame, masks.to_vec(), &effect_outer_frame, &effect_inner_frame));
+        }
+        let mut masks = masks.to_vec();
+        masks.extend(self.mask_clip());
+        let (raw_pixels, sprite_frame) = self.sprite.pixels();
+        // The compositor reads the whole frame through the pointer, sprites are free to return
+        // fewer pixels than their frame holds. They are reported when drawn.
+        if raw_pixels.len() < (sprite_frame.size.width.max(0) * sprite_frame.size.height.max(0)) as usize {
+            return;
+        }
+        items.push(self.render_item(raw_pixels.as_ptr(), parent_node_frame, masks, &self.frame, sprite_frame));
+    }
+
+    fn render_item(&self, raw_pixels_ptr:*const u32, parent_node_frame:&Rect, masks:Vec<MaskClip>, outer_frame:&Rect, inner_frame:&Rect) -> RenderItem {
+        RenderItem {
+            raw_pixels_ptr,
+            parent_node_frame : *parent_node_frame,
+            parent_transform : self.parent_transform,
+            outer_frame : *outer_frame,
+            inner_frame : *inner_frame,
+            transform : self.world_transform,
+            clip_to_bounds : self.clip_to_bounds,
+            masks,
+        }
     }
 
     fn generate_key() -> [u8;16] {
@@ -84,7 +224,28 @@ impl<'a, 'b : 'a> Node<'a> {
         key
     }
 
-    pub fn new_rect_node(float_frame:FloatRect, sprite:RectSprite) -> Node<'a> {
+    /// Node showing a sprite of any type, including sprites implemented outside of this crate.
+    pub fn new_with_sprite<S: Sprite<'a> + 'static>(float_frame:FloatRect, sprite:S) -> Node<'a> {
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
+    }
+
+    /// The sprite if it is a `S`.
+    pub fn sprite<S: Sprite<'a> + 'static>(&self) -> Option<&S> {
+        (*self.sprite).as_any().downcast_ref::<S>()
+    }
+
+    /// The sprite if it is a `S`. The node is drawn again, since the sprite is likely changed.
+    pub fn sprite_mut<S: Sprite<'a> + 'static>(&mut self) -> Option<&mut S> {
+        match (*self.sprite).as_any_mut().downcast_mut::<S>() {
+            Some(sprite) => {
+                self.need_draw = true;
+                Some(sprite)
+            },
+            None => None,
+        }
+    }
+
+    fn new_with_boxed_sprite(float_frame:FloatRect, sprite:Box<dyn Sprite<'a> + 'static>) -> Node<'a> {
         Node {
             key : Self::generate_key(),
             tag : String::new(),
@@ -93,37 +254,57 @@ impl<'a, 'b : 'a> Node<'a> {
             anchor_point: ANCHOR_POINT_CENTER,
             frame: RECT_ZERO,
             need_draw: true,
-            sprite: Box::new(sprite),
+            sprite,
             clip_to_bounds : true,
+            shadow : None,
+            glow : None,
+            effects : Vec::new(),
+            mask : None,
+            rotation : 0.0,
+            scale : SCALE_SINGLE,
+            skew : SKEW_ZERO,
+            world_transform : TRANSFORM_IDENTITY,
+            parent_transform : TRANSFORM_IDENTITY,
         }
     }
 
+    pub fn new_rect_node(float_frame:FloatRect, sprite:RectSprite) -> Node<'a> {
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
+    }
+
     pub fn new_text_node(float_frame:FloatRect, sprite:TextSprite) -> Node<'a> {
-         Node {
-             key : Self::generate_key(),
-             tag : String::new(),
-             visible : true,
-             float_frame : float_frame,
-             anchor_point: ANCHOR_POINT_CENTER,
-             frame: RECT_ZERO,
-             need_draw: true,
-             sprite: Box::new(sprite),
-             clip_to_bounds : true,
-        }
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
     }
 
     pub fn new_texture_node(float_frame:FloatRect, sprite:TextureSprite) -> Node<'a> {
-        Node {
-            key : Self::generate_key(),
-            tag : String::new(),
-            visible : true,
-            float_frame : float_frame,
-            anchor_point: ANCHOR_POINT_CENTER,
-            frame: RECT_ZERO,
-            need_draw: true,
-            sprite: Box::new(sprite),
-            clip_to_bounds : true,
-        }
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
+    }
+
+    pub fn new_shape_node(float_frame:FloatRect, sprite:ShapeSprite) -> Node<'a> {
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
+    }
+
+    pub fn new_nine_patch_node(float_frame:FloatRect, sprite:NinePatchSprite) -> Node<'a> {
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
+    }
+
+    pub fn new_sprite_sheet_node(float_frame:FloatRect, sprite:SpriteSheetSprite) -> Node<'a> {
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
     }
-}
 
+    pub fn new_canvas_node(float_frame:FloatRect, sprite:CanvasSprite) -> Node<'a> {
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
+    }
+
+    pub fn new_qr_code_node(float_frame:FloatRect, sprite:QrCodeSprite) -> Node<'a> {
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
+    }
+
+    pub fn new_barcode_node(float_frame:FloatRect, sprite:BarcodeSprite) -> Node<'a> {
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
+    }
+
+    pub fn new_chart_node(float_frame:FloatRect, sprite:ChartSprite) -> Node<'a> {
+        Self::new_with_boxed_sprite(float_frame, Box::new(sprite))
+    }
+}
@@ -0,0 +1,426 @@
+extern crate tiny_skia;
+
+use std::f32;
+use std::f32::consts::PI;
+
+use tiny_skia::{Path, PathBuilder};
+
+/// Parses SVG path data (`d` attribute syntax) into a path.
+pub fn parse_path_data(data: &str) -> Result<Path, String> {
+    let mut parser = PathDataParser {
+        data: data.as_bytes(),
+        offset: 0,
+    };
+    let mut builder = PathBuilder::new();
+
+    let mut current = (0.0f32, 0.0f32);
+    let mut subpath_start = (0.0f32, 0.0f32);
+    // Control point of the previous curve, used to reflect the first control point of S/s and T/t.
+    let mut last_cubic_control: Option<(f32, f32)> = None;
+    let mut last_quad_control: Option<(f32, f32)> = None;
+    let mut command = None;
+
+    loop {
+        parser.skip_separators();
+        if parser.at_end() {
+            break;
+        }
+        let repeated = match parser.command() {
+            Some(next_command) => {
+                command = Some(next_command);
+                false
+            }
+            None => true,
+        };
+        let current_command = match command {
+            Some(current_command) => current_command,
+            None => return Err(format!("Path data must start with a command ({}).", data)),
+        };
+        let relative = current_command.is_ascii_lowercase();
+        let base = if relative { current } else { (0.0, 0.0) };
+
+        let mut cubic_control = None;
+        let mut quad_control = None;
+
+        match current_command.to_ascii_uppercase() {
+            b'M' => {
+                let (x, y) = parser.pair()?;
+                current = (base.0 + x, base.1 + y);
+                subpath_start = current;
+                builder.move_to(current.0, current.1);
+                // Further pairs are implicit line commands.
+                command = Some(if relative { b'l' } else { b'L' });
+            }
+            b'L' => {
+                let (x, y) = parser.pair()?;
+                current = (base.0 + x, base.1 + y);
+                builder.line_to(current.0, current.1);
+            }
+            b'H' => {
+                let x = parser.number()?;
+                current = (base.0 + x, current.1);
+                builder.line_to(current.0, current.1);
+            }
+            b'V' => {
+                let y = parser.number()?;
+                current = (current.0, base.1 + y);
+                builder.line_to(current.0, current.1);
+            }
+            b'C' => {
+                let (x1, y1) = parser.pair()?;
+                let (x2, y2) = parser.pair()?;
+                let (x, y) = parser.pair()?;
+                let control = (base.0 + x2, base.1 + y2);
+                current = (base.0 + x, base.1 + y);
+                builder.cubic_to(base.0 + x1, base.1 + y1, control.0, control.1, current.0, current.1);
+                cubic_control = Some(control);
+            }
+            b'S' => {
+                let (x2, y2) = parser.pair()?;
+                let (x, y) = parser.pair()?;
+                let first = reflect(last_cubic_control, current);
+                let control = (base.0 + x2, base.1 + y2);
+                current = (base.0 + x, base.1 + y);
+                builder.cubic_to(first.0, first.1, control.0, control.1, current.0, current.1);
+                cubic_control = Some(control);
+            }
+            b'Q' => {
+                let (x1, y1) = parser.pair()?;
+                let (x, y) = parser.pair()?;
+                let control = (base.0 + x1, base.1 + y1);
+                current = (base.0 + x, base.1 + y);
+                builder.quad_to(control.0, control.1, current.0, current.1);
+                quad_control = Some(control);
+            }
+            b'T' => {
+                let (x, y) = parser.pair()?;
+                let control = reflect(last_quad_control, current);
+                current = (base.0 + x, base.1 + y);
+                builder.quad_to(control.0, control.1, current.0, current.1);
+                quad_control = Some(control);
+            }
+            b'A' => {
+                let (rx, ry) = parser.pair()?;
+                let x_axis_rotation = parser.number()?;
+                let large_arc = parser.flag()?;
+                let sweep = parser.flag()?;
+                let (x, y) = parser.pair()?;
+                let end = (base.0 + x, base.1 + y);
+                arc_to(&mut builder, current, end, rx, ry, x_axis_rotation, large_arc, sweep);
+                current = end;
+            }
+            b'Z' => {
+                // Close path takes no arguments, anything else after it is not a command.
+                if repeated {
+                    return Err(format!("Unexpected data after close path ({}).", data));
+                }
+                builder.close();
+                current = subpath_start;
+            }
+            _ => return Err(format!("Unknown path command ({}).", current_command as char)),
+        }
+
+        last_cubic_control = cubic_control;
+        last_quad_control = quad_control;
+    }
+
+    builder
+        .finish()
+        .ok_or_else(|| format!("Path data is empty ({}).", data))
+}
+
+fn reflect(control: Option<(f32, f32)>, current: (f32, f32)) -> (f32, f32) {
+    match control {
+        Some((x, y)) => (2.0 * current.0 - x, 2.0 * current.1 - y),
+        None => current,
+    }
+}
+
+/// Appends an elliptical arc from `start` to `end` as cubic curves, following the
+/// endpoint to center conversion of the SVG specification (appendix F.6).
+#[allow(clippy::too_many_arguments)]
+pub fn arc_to(
+    builder: &mut PathBuilder,
+    start: (f32, f32),
+    end: (f32, f32),
+    rx: f32,
+    ry: f32,
+    x_axis_rotation: f32,
+    large_arc: bool,
+    sweep: bool,
+) {
+    let mut rx = rx.abs();
+    let mut ry = ry.abs();
+    if rx == 0.0 || ry == 0.0 || (start.0 == end.0 && start.1 == end.1) {
+        builder.line_to(end.0, end.1);
+        return;
+    }
+
+    let (sin_phi, cos_phi) = x_axis_rotation.to_radians().sin_cos();
+    let dx = (start.0 - end.0) / 2.0;
+    let dy = (start.1 - end.1) / 2.0;
+    let x1 = cos_phi * dx + sin_phi * dy;
+    let y1 = -sin_phi * dx + cos_phi * dy;
+
+    let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if lambda > 1.0 {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+
+    let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let mut coefficient = (numerator / denominator).max(0.0).sqrt();
+    if large_arc == sweep {
+        coefficient = -coefficient;
+    }
+    let cx1 = coefficient * rx * y1 / ry;
+    let cy1 = -coefficient * ry * x1 / rx;
+
+    let cx = cos_phi * cx1 - sin_phi * cy1 + (start.0 + end.0) / 2.0;
+    let cy = sin_phi * cx1 + cos_phi * cy1 + (start.1 + end.1) / 2.0;
+
+    let start_angle = vector_angle(1.0, 0.0, (x1 - cx1) / rx, (y1 - cy1) / ry);
+    let mut sweep_angle = vector_angle(
+        (x1 - cx1) / rx,
+        (y1 - cy1) / ry,
+        (-x1 - cx1) / rx,
+        (-y1 - cy1) / ry,
+    );
+    if !sweep && sweep_angle > 0.0 {
+        sweep_angle -= 2.0 * PI;
+    } else if sweep && sweep_angle < 0.0 {
+        sweep_angle += 2.0 * PI;
+    }
+
+    append_arc(builder, (cx, cy), (rx, ry), x_axis_rotation, start_angle, sweep_angle);
+}
+
+/// Appends an elliptical arc given by its center as cubic curves. Angles are in radians,
+/// the path is expected to be positioned at the start of the arc already.
+pub fn append_arc(
+    builder: &mut PathBuilder,
+    center: (f32, f32),
+    radius: (f32, f32),
+    x_axis_rotation: f32,
+    start_angle: f32,
+    sweep_angle: f32,
+) {
+    let (sin_phi, cos_phi) = x_axis_rotation.to_radians().sin_cos();
+    let point = |angle: f32| -> (f32, f32) {
+        let (sin, cos) = angle.sin_cos();
+        let x = radius.0 * cos;
+        let y = radius.1 * sin;
+        (
+            center.0 + cos_phi * x - sin_phi * y,
+            center.1 + sin_phi * x + cos_phi * y,
+        )
+    };
+    let derivative = |angle: f32| -> (f32, f32) {
+        let (sin, cos) = angle.sin_cos();
+        let x = -radius.0 * sin;
+        let y = radius.1 * cos;
+        (cos_phi * x - sin_phi * y, sin_phi * x + cos_phi * y)
+    };
+
+    let segments = (sweep_angle.abs() / (PI / 2.0)).ceil().max(1.0) as i32;
+    let step = sweep_angle / segments as f32;
+    let handle = 4.0 / 3.0 * (step / 4.0).tan();
+
+    let mut angle = start_angle;
+    for _ in 0..segments {
+        let next_angle = angle + step;
+        let (x0, y0) = point(angle);
+        let (dx0, dy0) = derivative(angle);
+        let (x3, y3) = point(next_angle);
+        let (dx3, dy3) = derivative(next_angle);
+        builder.cubic_to(
+            x0 + handle * dx0,
+            y0 + handle * dy0,
+            x3 - handle * dx3,
+            y3 - handle * dy3,
+            x3,
+            y3,
+        );
+        angle = next_angle;
+    }
+}
+
+fn vector_angle(ux: f32, uy: f32, vx: f32, vy: f32) -> f32 {
+    let dot = ux * vx + uy * vy;
+    let length = (ux * ux + uy * uy).sqrt() * (vx * vx + vy * vy).sqrt();
+    let angle = (dot / length).clamp(-1.0, 1.0).acos();
+    if ux * vy - uy * vx < 0.0 {
+        -angle
+    } else {
+        angle
+    }
+}
+
+struct PathDataParser<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PathDataParser<'a> {
+    fn at_end(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    fn skip_separators(&mut self) {
+        while !self.at_end() {
+            let c = self.data[self.offset];
+            if c.is_ascii_whitespace() || c == b',' {
+                self.offset += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn command(&mut self) -> Option<u8> {
+        let c = self.data[self.offset];
+        if c.is_ascii_alphabetic() && c != b'e' && c != b'E' {
+            self.offset += 1;
+            Some(c)
+        } else {
+            None
+        }
+    }
+
+    fn number(&mut self) -> Result<f32, String> {
+        self.skip_separators();
+        let start = self.offset;
+        let mut seen_dot = false;
+        let mut seen_exponent = false;
+        while !self.at_end() {
+            let c = self.data[self.offset];
+            let sign_allowed = self.offset == start
+                || (seen_exponent && (self.data[self.offset - 1] == b'e' || self.data[self.offset - 1] == b'E'));
+            if c.is_ascii_digit() || ((c == b'-' || c == b'+') && sign_allowed) {
+                self.offset += 1;
+            } else if c == b'.' && !seen_dot && !seen_exponent {
+                seen_dot = true;
+                self.offset += 1;
+            } else if (c == b'e' || c == b'E') && !seen_exponent && self.offset > start {
+                seen_exponent = true;
+                self.offset += 1;
+            } else {
+                break;
+            }
+        }
+        let token = String::from_utf8_lossy(&self.data[start..self.offset]);
+        token
+            .parse::<f32>()
+            .map_err(|_| format!("Wrong number in path data at {} ({}).", start, token))
+    }
+
+    fn pair(&mut self) -> Result<(f32, f32), String> {
+        let x = self.number()?;
+        let y = self.number()?;
+        Ok((x, y))
+    }
+
+    fn flag(&mut self) -> Result<bool, String> {
+        self.skip_separators();
+        if !self.at_end() {
+            let c = self.data[self.offset];
+            if c == b'0' || c == b'1' {
+                self.offset += 1;
+                return Ok(c == b'1');
+            }
+        }
+        Err(format!("Wrong arc flag in path data at {}.", self.offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tiny_skia::PathSegment;
+    use tiny_skia::PathSegment::*;
+
+    fn point(x: f32, y: f32) -> tiny_skia::Point {
+        tiny_skia::Point::from_xy(x, y)
+    }
+
+    fn segments(data: &str) -> Vec<PathSegment> {
+        parse_path_data(data).unwrap().segments().collect()
+    }
+
+    #[test]
+    fn extra_move_pairs_are_lines() {
+        assert_eq!(
+            segments("M 0 0 10 0 10 10 Z"),
+            vec![MoveTo(point(0.0, 0.0)), LineTo(point(10.0, 0.0)), LineTo(point(10.0, 10.0)), Close]
+        );
+        assert_eq!(
+            segments("m 1 1 2 0 0 2 z"),
+            vec![MoveTo(point(1.0, 1.0)), LineTo(point(3.0, 1.0)), LineTo(point(3.0, 3.0)), Close]
+        );
+    }
+
+    #[test]
+    fn relative_commands_follow_the_current_point() {
+        assert_eq!(
+            segments("M10 10 h5 v5 H0 V0 l1 1"),
+            vec![
+                MoveTo(point(10.0, 10.0)),
+                LineTo(point(15.0, 10.0)),
+                LineTo(point(15.0, 15.0)),
+                LineTo(point(0.0, 15.0)),
+                LineTo(point(0.0, 0.0)),
+                LineTo(point(1.0, 1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn smooth_curves_reflect_the_previous_control_point() {
+        assert_eq!(
+            segments("M0 0 C0 10 10 10 10 0 S20 -10 20 0"),
+            vec![
+                MoveTo(point(0.0, 0.0)),
+                CubicTo(point(0.0, 10.0), point(10.0, 10.0), point(10.0, 0.0)),
+                CubicTo(point(10.0, -10.0), point(20.0, -10.0), point(20.0, 0.0)),
+            ]
+        );
+        assert_eq!(
+            segments("M0 0 Q5 10 10 0 T20 0"),
+            vec![
+                MoveTo(point(0.0, 0.0)),
+                QuadTo(point(5.0, 10.0), point(10.0, 0.0)),
+                QuadTo(point(15.0, -10.0), point(20.0, 0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_may_run_together() {
+        assert_eq!(
+            segments("M.5.5L1e1-2"),
+            vec![MoveTo(point(0.5, 0.5)), LineTo(point(10.0, -2.0))]
+        );
+    }
+
+    #[test]
+    fn arcs_end_at_their_end_point() {
+        let path = parse_path_data("M0 0 A10 10 0 0 1 20 0").unwrap();
+        match path.segments().last() {
+            Some(CubicTo(_, _, end)) => {
+                assert!((end.x - 20.0).abs() < 1e-3 && end.y.abs() < 1e-3);
+            }
+            _ => panic!("arc without curves"),
+        }
+    }
+
+    #[test]
+    fn wrong_path_data_is_rejected() {
+        assert!(parse_path_data("M0 0 L1 1 Z 5").is_err());
+        assert!(parse_path_data("0 0 L1 1").is_err());
+        assert!(parse_path_data("M0 0 L1").is_err());
+        assert!(parse_path_data("M0 0 A1 1 0 2 0 1 1").is_err());
+        assert!(parse_path_data("M0 0 X1 1").is_err());
+    }
+}
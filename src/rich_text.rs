@@ -0,0 +1,172 @@
+extern crate xml;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+use color::Color;
+use font::TextFont;
+use scene_bundle::resolve_color_from_value;
+
+/// Style of a span of rich text, unset values come from the `TextSprite`.
+#[derive(Clone, Default, PartialEq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub color: Option<Color>,
+    /// Font size in pixels.
+    pub font_size: Option<f32>,
+    /// Font tried before the font and fallback fonts of the sprite.
+    pub font: Option<TextFont>,
+}
+
+#[derive(Clone)]
+pub struct TextSpan {
+    pub text: String,
+    pub style: TextStyle,
+}
+
+impl TextSpan {
+    pub fn new(text: &str, style: TextStyle) -> TextSpan {
+        TextSpan {
+            text: text.to_string(),
+            style,
+        }
+    }
+}
+
+/// Parses inline markup like `Hello <b>bold</b> and <color value="red">red</color>` into
+/// spans. Supported elements are `<b>`, `<i>`, `<color value>`, `<size value>` in pixels,
+/// `<font value>` with a font file relative to `font_dir` and `<br/>` for a line break.
+/// Whitespace is collapsed to single spaces.
+pub fn parse_markup(markup: &str, font_dir: &Path) -> Result<Vec<TextSpan>, String> {
+    let document = format!("<markup>{}</markup>", markup);
+    let mut builder = MarkupBuilder::new(font_dir);
+    // Skips the start of the document and of the wrapping element.
+    for event in EventReader::new(document.as_bytes()).into_iter().skip(2) {
+        match event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => builder.start(&name.local_name, &attributes),
+            Ok(XmlEvent::EndElement { .. }) => builder.end(),
+            Ok(XmlEvent::Characters(text)) | Ok(XmlEvent::Whitespace(text)) | Ok(XmlEvent::CData(text)) => {
+                builder.text(&text)
+            }
+            Err(e) => return Err(format!("{}", e)),
+            _ => {}
+        }
+    }
+    Ok(builder.finish())
+}
+
+/// Elements `parse_markup` supports.
+pub(crate) fn is_markup_element(name: &str) -> bool {
+    ["b", "i", "color", "size", "font", "br"].contains(&name)
+}
+
+/// Collects spans from the events of markup elements, as they are read from scene.xml.
+pub(crate) struct MarkupBuilder {
+    spans: Vec<TextSpan>,
+    styles: Vec<TextStyle>,
+    font_dir: PathBuf,
+    after_space: bool,
+}
+
+impl MarkupBuilder {
+    pub(crate) fn new(font_dir: &Path) -> MarkupBuilder {
+        MarkupBuilder {
+            spans: Vec::new(),
+            styles: vec![TextStyle::default()],
+            font_dir: font_dir.to_path_buf(),
+            after_space: true,
+        }
+    }
+
+    /// An element opened and not yet ended.
+    pub(crate) fn is_open(&self) -> bool {
+        self.styles.len() > 1
+    }
+
+    pub(crate) fn start(&mut self, name: &str, attributes: &[OwnedAttribute]) {
+        let mut style = self.styles[self.styles.len() - 1].clone();
+        let value = attributes
+            .iter()
+            .find(|attribute| attribute.name.local_name == "value")
+            .map(|attribute| attribute.value.trim());
+        match (name, value) {
+            ("b", _) => style.bold = true,
+            ("i", _) => style.italic = true,
+            ("br", _) => {
+                self.push_text("\n", &style);
+                self.after_space = true;
+            }
+            ("color", Some(value)) => match resolve_color_from_value(value) {
+                Some(color) => style.color = Some(color),
+                None => println!("Wrong color value ({})", value),
+            },
+            ("size", Some(value)) => match value.parse::<f32>() {
+                Ok(size) if size > 0.0 => style.font_size = Some(size),
+                _ => println!("Wrong size value ({})", value),
+            },
+            ("font", Some(value)) => {
+                let font = fs::File::open(self.font_dir.join(value))
+                    .map_err(|e| format!("{:?}", e))
+                    .and_then(|mut file| TextFont::from_file(&mut file));
+                match font {
+                    Ok(font) => style.font = Some(font),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            _ => println!("Unknown markup element <{}>", name),
+        }
+        // Unknown elements keep the style, so their end still pops a style.
+        self.styles.push(style);
+    }
+
+    pub(crate) fn end(&mut self) {
+        if self.is_open() {
+            self.styles.pop();
+        }
+    }
+
+    pub(crate) fn text(&mut self, text: &str) {
+        let mut collapsed = String::new();
+        for c in text.chars() {
+            if c.is_whitespace() {
+                if !self.after_space {
+                    collapsed.push(' ');
+                }
+                self.after_space = true;
+            } else {
+                collapsed.push(c);
+                self.after_space = false;
+            }
+        }
+        let style = self.styles[self.styles.len() - 1].clone();
+        self.push_text(&collapsed, &style);
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<TextSpan> {
+        // Drop the space collapsed from trailing whitespace.
+        while let Some(span) = self.spans.last_mut() {
+            let length = span.text.trim_end_matches(' ').len();
+            span.text.truncate(length);
+            if !span.text.is_empty() {
+                break;
+            }
+            self.spans.pop();
+        }
+        self.spans
+    }
+
+    fn push_text(&mut self, text: &str, style: &TextStyle) {
+        if text.is_empty() {
+            return;
+        }
+        if let Some(span) = self.spans.last_mut().filter(|span| span.style == *style) {
+            span.text.push_str(text);
+            return;
+        }
+        self.spans.push(TextSpan::new(text, style.clone()));
+    }
+}
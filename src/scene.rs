@@ -1,14 +1,26 @@
+extern crate rayon;
+
 use std::cell;
 use std::collections::*;
+use std::rc::Rc;
 use std::time;
 use std::thread::sleep;
 use screen_writer::{ScreenInfo, ScreenWriter};
 
+use compositor;
+use debug_overlay::{DebugNodeInfo, DebugOverlay};
+use font::SharedFontList;
+use glyph_cache::{GlyphCache, SharedGlyphCache, DEFAULT_GLYPH_CACHE_BUDGET};
+use mask::MaskClip;
 use dimension::*;
 use node::*;
+use sprite::RenderItem;
+use texture_cache::{SharedTextureCache, TextureCache, DEFAULT_TEXTURE_CACHE_BUDGET};
+use transform::{Transform, TRANSFORM_IDENTITY};
 
 pub struct SceneState {
     pub running: bool,
+    pub show_debug_overlay: bool,
 }
 
 type OnEveryFrame = fn(state: SceneState) -> SceneState;
@@ -19,6 +31,11 @@ pub struct Scene<'a> {
     pub nodes: HashMap<NodeKey, cell::RefCell<Node<'a>>>,
     pub hierarchy: HashMap<NodeKey, cell::RefCell<Vec<NodeKey>>>,
     root_node_key: NodeKey,
+    thread_pool: Option<rayon::ThreadPool>,
+    debug_overlay: cell::RefCell<Option<DebugOverlay>>,
+    texture_cache: SharedTextureCache,
+    fallback_fonts: SharedFontList,
+    glyph_cache: SharedGlyphCache,
     fps: u32,
     dirty: bool,
 }
@@ -33,6 +50,11 @@ impl<'a> Scene<'a> {
             nodes: HashMap::new(),
             hierarchy: HashMap::new(),
             root_node_key: EMPTY_NODE_KEY,
+            thread_pool: None,
+            debug_overlay: cell::RefCell::new(None),
+            texture_cache: TextureCache::new_shared(DEFAULT_TEXTURE_CACHE_BUDGET),
+            fallback_fonts: Rc::new(cell::RefCell::new(Vec::new())),
+            glyph_cache: GlyphCache::new_shared(DEFAULT_GLYPH_CACHE_BUDGET),
         }
     }
 
@@ -67,13 +89,14 @@ impl<'a> Scene<'a> {
 
         if let Some(ref root_node) = self.nodes.get(&self.root_node_key) {
             let mut root_node_mut = root_node.borrow_mut();
-            root_node_mut.layout(frame_rect, screen_info);
+            root_node_mut.layout(frame_rect, &TRANSFORM_IDENTITY, screen_info);
 
             if let Some(key_cell) = self.hierarchy.get(&root_node_mut.key) {
                 let mut children_keys = key_cell.borrow_mut();
                 self.layout_nodes(
                     &frame_rect,
                     &root_node_mut.anchor_point,
+                    &root_node_mut.world_transform,
                     &children_keys,
                     screen_info,
                 );
@@ -85,6 +108,7 @@ impl<'a> Scene<'a> {
         &self,
         parent_node_rect: &Rect,
         parent_acnhor_point: &AnchorPoint,
+        parent_transform: &Transform,
         nodes_keys: &Vec<NodeKey>,
         screen_info: &ScreenInfo,
     ) {
@@ -95,13 +119,14 @@ impl<'a> Scene<'a> {
                     let frame_rect =
                         node_mut.fix_rect_for_parent_fix_rect(parent_node_rect, &parent_acnhor_point);
 
-                    node_mut.layout(frame_rect, screen_info);
+                    node_mut.layout(frame_rect, parent_transform, screen_info);
 
                     if let Some(key_cell) = self.hierarchy.get(&node_mut.key) {
                         let mut children_keys = key_cell.borrow_mut();
                         self.layout_nodes(
                             &frame_rect,
                             &node_mut.anchor_point,
+                            &node_mut.world_transform,
                             &children_keys,
                             screen_info,
                         );
@@ -111,48 +136,110 @@ impl<'a> Scene<'a> {
         }
     }
 
-    fn draw_root_node(&self, screen_info: &ScreenInfo) {
+    /// Advances time based sprites of visible nodes and applies changed node transforms.
+    /// Returns true when the frame needs rendering.
+    fn update_nodes(&self, clock: time::Duration) -> bool {
+        let mut updated = false;
+        if let Some(root_node) = self.nodes.get(&self.root_node_key) {
+            let mut pending_keys = vec![(root_node.borrow().key, TRANSFORM_IDENTITY)];
+            while let Some((node_key, parent_transform)) = pending_keys.pop() {
+                if let Some(node) = self.nodes.get(&node_key) {
+                    let mut node_mut = node.borrow_mut();
+                    if !node_mut.visible {
+                        continue;
+                    }
+                    updated |= node_mut.update(clock);
+                    updated |= node_mut.update_transform(&parent_transform);
+                    if let Some(key_cell) = self.hierarchy.get(&node_key) {
+                        let world_transform = node_mut.world_transform;
+                        pending_keys.extend(key_cell.borrow().iter().map(|key| (*key, world_transform)));
+                    }
+                }
+            }
+        }
+        updated
+    }
+
+    fn draw_root_node(&self, screen_info: &ScreenInfo, redrawn_keys: &mut HashSet<NodeKey>) {
         if let Some(ref root_node) = self.nodes.get(&self.root_node_key) {
             let mut root_node_mut = root_node.borrow_mut();
-            root_node_mut.draw_if_need(screen_info);
+            if root_node_mut.draw_if_need(screen_info) {
+                redrawn_keys.insert(root_node_mut.key);
+            }
 
             if let Some(key_cell) = self.hierarchy.get(&root_node_mut.key) {
                 let mut children_keys = key_cell.borrow_mut();
-                self.draw_nodes(&children_keys, screen_info);
+                self.draw_nodes(&children_keys, screen_info, redrawn_keys);
             }
         }
     }
 
-    fn draw_nodes(&self, nodes_keys: &Vec<NodeKey>, screen_info: &ScreenInfo) {
+    fn draw_nodes(&self, nodes_keys: &Vec<NodeKey>, screen_info: &ScreenInfo, redrawn_keys: &mut HashSet<NodeKey>) {
         for node_key in nodes_keys {
             if let Some(ref node) = self.nodes.get(node_key) {
                 let mut node_mut = node.borrow_mut();
                 if node_mut.visible {
-                    node_mut.draw_if_need(screen_info);
+                    if node_mut.draw_if_need(screen_info) {
+                        redrawn_keys.insert(node_mut.key);
+                    }
 
                     if let Some(key_cell) = self.hierarchy.get(&node_mut.key) {
                         let mut children_keys = key_cell.borrow_mut();
-                        self.draw_nodes(&children_keys, screen_info);
+                        self.draw_nodes(&children_keys, screen_info, redrawn_keys);
                     }
                 }
             }
         }
     }
 
-    fn render_root_node(&self, screen_info: &ScreenInfo) {
-        if let Some(ref root_node) = self.nodes.get(&self.root_node_key) {
-            let mut root_node_mut = root_node.borrow_mut();
-            root_node_mut.draw_if_need(screen_info);
-            let root_node_frame = root_node_mut.frame;
-            root_node_mut.render(
-                &root_node_frame,
-                screen_info,
-                self.canvas_buffer.borrow_mut().as_mut_ptr() as *mut u32,
-            );
+    fn debug_root_node(&self, redrawn_keys: &HashSet<NodeKey>, infos: &mut Vec<DebugNodeInfo>) {
+        if let Some(root_node) = self.nodes.get(&self.root_node_key) {
+            let root_node = root_node.borrow();
+            infos.push(Self::debug_node_info(&root_node, redrawn_keys));
 
-            if let Some(key_cell) = self.hierarchy.get(&root_node_mut.key) {
-                let mut children_keys = key_cell.borrow_mut();
-                self.render_nodes(&root_node_frame, &children_keys, screen_info);
+            if let Some(key_cell) = self.hierarchy.get(&root_node.key) {
+                let children_keys = key_cell.borrow();
+                self.debug_nodes(&children_keys, redrawn_keys, infos);
+            }
+        }
+    }
+
+    fn debug_nodes(&self, nodes_keys: &Vec<NodeKey>, redrawn_keys: &HashSet<NodeKey>, infos: &mut Vec<DebugNodeInfo>) {
+        for node_key in nodes_keys {
+            if let Some(node) = self.nodes.get(node_key) {
+                let node = node.borrow();
+                if node.visible {
+                    infos.push(Self::debug_node_info(&node, redrawn_keys));
+
+                    if let Some(key_cell) = self.hierarchy.get(&node.key) {
+                        let children_keys = key_cell.borrow();
+                        self.debug_nodes(&children_keys, redrawn_keys, infos);
+                    }
+                }
+            }
+        }
+    }
+
+    fn debug_node_info(node: &Node, redrawn_keys: &HashSet<NodeKey>) -> DebugNodeInfo {
+        DebugNodeInfo {
+            tag: node.tag.clone(),
+            sprite_type: node.sprite_type(),
+            frame: node.frame,
+            bounds: node.bounds(),
+            redrawn: redrawn_keys.contains(&node.key),
+        }
+    }
+
+    fn render_root_node(&self, items: &mut Vec<RenderItem>) {
+        if let Some(ref root_node) = self.nodes.get(&self.root_node_key) {
+            let root_node = root_node.borrow();
+            let root_node_frame = root_node.frame;
+            root_node.render(&root_node_frame, &[], items);
+
+            if let Some(key_cell) = self.hierarchy.get(&root_node.key) {
+                let children_keys = key_cell.borrow();
+                let masks: Vec<MaskClip> = root_node.mask_clip().into_iter().collect();
+                self.render_nodes(&root_node_frame, &masks, &children_keys, items);
             }
         }
     }
@@ -160,22 +247,21 @@ impl<'a> Scene<'a> {
     fn render_nodes(
         &self,
         parent_node_frame: &Rect,
+        masks: &[MaskClip],
         nodes_keys: &Vec<NodeKey>,
-        screen_info: &ScreenInfo,
+        items: &mut Vec<RenderItem>,
     ) {
         for node_key in nodes_keys {
             if let Some(ref node) = self.nodes.get(node_key) {
-                let mut node_mut = node.borrow_mut();
-                if node_mut.visible {
-                    node_mut.render(
-                        parent_node_frame,
-                        screen_info,
-                        self.canvas_buffer.borrow_mut().as_mut_ptr() as *mut u32,
-                    );
-
-                    if let Some(key_cell) = self.hierarchy.get(&node_mut.key) {
-                        let mut children_keys = key_cell.borrow_mut();
-                        self.render_nodes(&node_mut.frame, &children_keys, screen_info);
+                let node = node.borrow();
+                if node.visible {
+                    node.render(parent_node_frame, masks, items);
+
+                    if let Some(key_cell) = self.hierarchy.get(&node.key) {
+                        let children_keys = key_cell.borrow();
+                        let mut children_masks = masks.to_vec();
+                        children_masks.extend(node.mask_clip());
+                        self.render_nodes(&node.frame, &children_masks, &children_keys, items);
                     }
                 }
             }
@@ -183,12 +269,105 @@ impl<'a> Scene<'a> {
     }
 
     fn render_frame(&self, screen_info: &ScreenInfo) {
-        self.draw_root_node(screen_info);
-        self.render_root_node(screen_info);
+        let start_time = time::Instant::now();
+        let mut debug_overlay = self.debug_overlay.borrow_mut();
+        if let Some(ref mut debug_overlay) = *debug_overlay {
+            debug_overlay.begin_frame(start_time);
+        }
+
+        let mut redrawn_keys = HashSet::new();
+        self.draw_root_node(screen_info, &mut redrawn_keys);
+
+        let mut items = Vec::new();
+        self.render_root_node(&mut items);
+
+        if let Some(ref mut debug_overlay) = *debug_overlay {
+            let mut infos = Vec::new();
+            self.debug_root_node(&redrawn_keys, &mut infos);
+            debug_overlay.draw(&infos, screen_info);
+            debug_overlay.render(&mut items);
+        }
+        {
+            let mut canvas_buffer = self.canvas_buffer.borrow_mut();
+            let canvas_ptr = canvas_buffer.as_mut_ptr();
+            match self.thread_pool {
+                Some(ref thread_pool) => {
+                    compositor::composite_parallel(thread_pool, &items, screen_info, canvas_ptr)
+                }
+                None => {
+                    let screen_rect = Rect {
+                        pos: POS_ZERO,
+                        size: Size {
+                            width: screen_info.xres,
+                            height: screen_info.yres,
+                        },
+                    };
+                    compositor::composite(&items, &screen_rect, screen_info, canvas_ptr)
+                }
+            }
+        }
         // FIX IT
         if let Some(ref writer) = self.writer {
             writer.write(self.canvas_buffer.borrow_mut().to_vec());
         }
+
+        if let Some(ref mut debug_overlay) = *debug_overlay {
+            debug_overlay.end_frame(start_time.elapsed());
+        }
+    }
+
+    /// Shows or hides the debug overlay with node tags, frames, sprite types, redrawn nodes and
+    /// an FPS graph. While it is shown the scene is rendered on every frame.
+    pub fn set_debug_overlay(&self, show: bool) {
+        let mut debug_overlay = self.debug_overlay.borrow_mut();
+        if show != debug_overlay.is_some() {
+            *debug_overlay = if show { Some(DebugOverlay::new()) } else { None };
+        }
+    }
+
+    pub fn is_debug_overlay_shown(&self) -> bool {
+        self.debug_overlay.borrow().is_some()
+    }
+
+    /// Cache shared by the texture sprites of this scene.
+    pub fn texture_cache(&self) -> SharedTextureCache {
+        self.texture_cache.clone()
+    }
+
+    /// Memory budget of the texture cache in bytes.
+    pub fn set_texture_cache_budget(&self, budget: usize) {
+        self.texture_cache.borrow_mut().set_budget(budget);
+    }
+
+    /// Cache of the glyphs rasterized by the text sprites of this scene.
+    pub fn glyph_cache(&self) -> SharedGlyphCache {
+        self.glyph_cache.clone()
+    }
+
+    /// Memory budget of the glyph cache in bytes.
+    pub fn set_glyph_cache_budget(&self, budget: usize) {
+        self.glyph_cache.borrow_mut().set_budget(budget);
+    }
+
+    /// Fonts the text sprites of this scene draw the chars their own fonts lack from.
+    pub fn fallback_fonts(&self) -> SharedFontList {
+        self.fallback_fonts.clone()
+    }
+
+    /// Composites frames on a pool of `threads` threads, each rendering horizontal bands of
+    /// the canvas. `0` or `1` switches back to compositing on the scene thread.
+    pub fn set_compositor_threads(&mut self, threads: usize) {
+        self.thread_pool = if threads > 1 {
+            match rayon::ThreadPoolBuilder::new().num_threads(threads).build() {
+                Ok(thread_pool) => Some(thread_pool),
+                Err(e) => {
+                    println!("{:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
     }
 
     pub fn run_with_state(&mut self, on_every_frame_function: OnEveryFrame) {
@@ -200,8 +379,12 @@ impl<'a> Scene<'a> {
             let frame_duration = time::Duration::from_millis((1000 / self.fps) as u64);
             let mut counter = 0;
             self.layout(screen_info);
+            let scene_start = time::Instant::now();
 
-            let mut state = SceneState { running: true };
+            let mut state = SceneState {
+                running: true,
+                show_debug_overlay: self.is_debug_overlay_shown(),
+            };
 
             loop {
                 state = on_every_frame_function(state);
@@ -209,9 +392,16 @@ impl<'a> Scene<'a> {
                 if !state.running {
                     break;
                 }
+                if state.show_debug_overlay != self.is_debug_overlay_shown() {
+                    self.set_debug_overlay(state.show_debug_overlay);
+                    self.dirty = true;
+                }
 
                 let start_time = time::SystemTime::now();
-                if self.dirty {
+                if self.update_nodes(scene_start.elapsed()) {
+                    self.dirty = true;
+                }
+                if self.dirty || self.is_debug_overlay_shown() {
                     self.render_frame(screen_info);
                     self.dirty = false;
                 }
@@ -239,9 +429,13 @@ impl<'a> Scene<'a> {
             let frame_duration = time::Duration::from_millis((1000 / self.fps) as u64);
             let mut counter = 0;
             self.layout(screen_info);
+            let scene_start = time::Instant::now();
             loop {
                 let start_time = time::SystemTime::now();
-                if self.dirty {
+                if self.update_nodes(scene_start.elapsed()) {
+                    self.dirty = true;
+                }
+                if self.dirty || self.is_debug_overlay_shown() {
                     self.render_frame(screen_info);
                     self.dirty = false;
                 }
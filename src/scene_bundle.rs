@@ -9,15 +9,27 @@ use std::f32;
 use xml::reader::{EventReader, XmlEvent};
 use image::{imageops};
 
+use barcode::{BarcodeSprite, ErrorCorrection, QrCodeSprite, Symbology};
+use chart::{ChartKind, ChartSprite};
+use clock::{Clock, TimeZone};
 use color::Color;
 use color;
 use dimension::*;
+use effect::{Glow, Shadow};
+use mask::{Mask, MaskShape};
+use image_filter::ImageFilter;
+use nine_patch::{Insets, NinePatchSprite};
 use node;
 use resource;
+use rich_text::{is_markup_element, MarkupBuilder, TextSpan};
 use scene::Scene;
 use shape;
+use sprite_sheet::{PlaybackMode, SpriteSheetSprite};
 use text;
-use texture::TextureSprite;
+use texture::{Fit, TextureSprite};
+use texture_cache::SharedTextureCache;
+use font::{SharedFontList, TextFont};
+use glyph_cache::SharedGlyphCache;
 
 impl<'a> Scene<'a> {
     pub fn new_from_bundle(path: &str) -> Result<Scene<'a>, String> {
@@ -43,34 +55,88 @@ impl<'a> Scene<'a> {
         let mut scene = Scene::new();
 
         let mut current_keys: Vec<node::NodeKey> = Vec::new();
+        // Text and clock nodes with the markup read in them so far, innermost last.
+        let mut text_markups: Vec<(node::NodeKey, String, MarkupBuilder)> = Vec::new();
 
         for e in parser {
             match e {
                 Ok(XmlEvent::StartElement {
                     name, attributes, ..
                 }) => {
+                    // Other elements in a text are child nodes of the text node.
+                    if let Some(markup) = current_markup(&mut text_markups, &current_keys) {
+                        if markup.is_open() || is_markup_element(&name.local_name) {
+                            markup.start(&name.local_name, &attributes);
+                            continue;
+                        }
+                    }
                     if name.local_name == "scene" {
-                        let root_node = process_scene_attributes(&scene, attributes);
+                        let root_node = process_scene_attributes(&scene, attributes, scene_bundle);
                         current_keys.push(root_node.key);
                         scene.set_root_node(root_node);
                     } else if name.local_name == "box" {
-                        let node = process_box_attributes(attributes);
+                        let node = process_box_attributes(attributes, scene_bundle);
                         let node_key = node.key;
                         if let Some(parent_key) = current_keys.last() {
                             let parent_key = node::Node::deref_node_key(parent_key);
                             scene.add_node(node, parent_key);
                         }
                         current_keys.push(node_key);
-                    } else if name.local_name == "text" {
-                        let node = process_text_attributes(attributes, &scene_bundle);
+                    } else if name.local_name == "text" || name.local_name == "clock" {
+                        let node = process_text_attributes(&name.local_name, attributes, scene_bundle, &scene.fallback_fonts(), &scene.glyph_cache());
                         let node_key = node.key;
-                        if let Some(parent_key) = current_keys.last().clone() {
+                        if let Some(parent_key) = current_keys.last() {
+                            let parent_key = node::Node::deref_node_key(parent_key);
+                            scene.add_node(node, parent_key);
+                        }
+                        current_keys.push(node_key);
+                        // The markup content styles the text at the end of the element.
+                        text_markups.push((node_key, name.local_name, MarkupBuilder::new(&scene_bundle.target_path())));
+                    } else if name.local_name == "line" || name.local_name == "ellipse"
+                        || name.local_name == "path" || name.local_name == "polyline"
+                        || name.local_name == "polygon"
+                    {
+                        let node = process_shape_attributes(&name.local_name, attributes, scene_bundle);
+                        let node_key = node.key;
+                        if let Some(parent_key) = current_keys.last() {
+                            let parent_key = node::Node::deref_node_key(parent_key);
+                            scene.add_node(node, parent_key);
+                        }
+                        current_keys.push(node_key);
+                    } else if name.local_name == "ninepatch" {
+                        let node = process_nine_patch_attributes(attributes, scene_bundle);
+                        let node_key = node.key;
+                        if let Some(parent_key) = current_keys.last() {
+                            let parent_key = node::Node::deref_node_key(parent_key);
+                            scene.add_node(node, parent_key);
+                        }
+                        current_keys.push(node_key);
+                    } else if name.local_name == "spritesheet" {
+                        let node = process_sprite_sheet_attributes(attributes, scene_bundle);
+                        let node_key = node.key;
+                        if let Some(parent_key) = current_keys.last() {
+                            let parent_key = node::Node::deref_node_key(parent_key);
+                            scene.add_node(node, parent_key);
+                        }
+                        current_keys.push(node_key);
+                    } else if name.local_name == "qrcode" || name.local_name == "barcode" {
+                        let node = process_code_attributes(&name.local_name, attributes, scene_bundle);
+                        let node_key = node.key;
+                        if let Some(parent_key) = current_keys.last() {
+                            let parent_key = node::Node::deref_node_key(parent_key);
+                            scene.add_node(node, parent_key);
+                        }
+                        current_keys.push(node_key);
+                    } else if name.local_name == "chart" {
+                        let node = process_chart_attributes(attributes, scene_bundle);
+                        let node_key = node.key;
+                        if let Some(parent_key) = current_keys.last() {
                             let parent_key = node::Node::deref_node_key(parent_key);
                             scene.add_node(node, parent_key);
                         }
                         current_keys.push(node_key);
                     } else if name.local_name == "image" {
-                        let node = process_texture_attributes(attributes, &scene_bundle);
+                        let node = process_texture_attributes(attributes, scene_bundle, &scene.texture_cache());
                         let node_key = node.key;
                         if let Some(parent_key) = current_keys.last().clone() {
                             let parent_key = node::Node::deref_node_key(parent_key);
@@ -79,7 +145,21 @@ impl<'a> Scene<'a> {
                         current_keys.push(node_key);
                     }
                 }
+                Ok(XmlEvent::Characters(text)) | Ok(XmlEvent::Whitespace(text)) | Ok(XmlEvent::CData(text)) => {
+                    if let Some(markup) = current_markup(&mut text_markups, &current_keys) {
+                        markup.text(&text);
+                    }
+                }
                 Ok(XmlEvent::EndElement { .. }) => {
+                    if let Some(markup) = current_markup(&mut text_markups, &current_keys) {
+                        if markup.is_open() {
+                            markup.end();
+                            continue;
+                        }
+                        if let Some((text_key, element_name, markup)) = text_markups.pop() {
+                            apply_text_markup(&scene, &text_key, &element_name, markup.finish());
+                        }
+                    }
                     current_keys.pop();
                 }
                 Err(e) => {
@@ -94,8 +174,9 @@ impl<'a> Scene<'a> {
 }
 
 fn process_scene_attributes<'a>(
-    _scene: &Scene,
+    scene: &Scene,
     attributes: Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
 ) -> node::Node<'a> {
     let tag = resolve_text_from_attributes("tag", &attributes, String::new());
     let anchor_point = resolve_anchor_from_attributes(&attributes, ANCHOR_POINT_CENTER);
@@ -103,6 +184,11 @@ fn process_scene_attributes<'a>(
     let color =
         resolve_color_from_attributes(&attributes, color::GRAY).color_with_alpha_float(alpha);
 
+    scene
+        .fallback_fonts()
+        .borrow_mut()
+        .extend(resolve_fonts_from_attributes("fallback-fonts", &attributes, scene_bundle));
+
     let mut box_sprite = shape::RectSprite::new();
     box_sprite.color = color;
 
@@ -112,16 +198,33 @@ fn process_scene_attributes<'a>(
     node
 }
 
-fn process_box_attributes<'a>(attributes: Vec<xml::attribute::OwnedAttribute>) -> node::Node<'a> {
-    let visible = resolve_bool_from_attributes("visible", &attributes, true);
-    let tag = resolve_text_from_attributes("tag", &attributes, String::new());
+/// Node attributes common to all elements but the scene.
+fn apply_node_attributes(
+    node: &mut node::Node,
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
+) {
+    node.visible = resolve_bool_from_attributes("visible", attributes, true);
+    node.tag = resolve_text_from_attributes("tag", attributes, String::new());
+    node.anchor_point = resolve_anchor_from_attributes(attributes, ANCHOR_POINT_CENTER);
+    node.clip_to_bounds = resolve_bool_from_attributes("clip_to_bounds", attributes, false);
+    node.shadow = resolve_shadow_from_attributes("shadow", attributes);
+    node.glow = resolve_glow_from_attributes(attributes);
+    node.rotation = resolve_float_from_attributes("rotation", attributes, 0.0);
+    node.scale = resolve_scale_from_attributes(attributes, SCALE_SINGLE);
+    node.skew = resolve_skew_from_attributes(attributes, SKEW_ZERO);
+    node.mask = resolve_mask_from_attributes(attributes, scene_bundle);
+}
+
+fn process_box_attributes<'a>(
+    attributes: Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
+) -> node::Node<'a> {
     let alpha = resolve_float_from_attributes("alpha", &attributes, 1.0);
     let color =
         resolve_color_from_attributes(&attributes, color::GRAY).color_with_alpha_float(alpha);
     let pos = resolve_position_from_attributes(&attributes, FLOAT_POS_ZERO);
     let size = resolve_size_from_attributes(&attributes, FLOAT_SIZE_HALF);
-    let anchor_point = resolve_anchor_from_attributes(&attributes, ANCHOR_POINT_CENTER);
-    let clip_to_bounds = resolve_bool_from_attributes("clip_to_bounds", &attributes, false);
 
     let mut box_sprite = shape::RectSprite::new();
     box_sprite.color = color;
@@ -132,36 +235,86 @@ fn process_box_attributes<'a>(attributes: Vec<xml::attribute::OwnedAttribute>) -
         },
         box_sprite,
     );
-    node.anchor_point = anchor_point;
-    node.tag = tag;
-    node.clip_to_bounds = clip_to_bounds;
-    node.visible = visible;
+    apply_node_attributes(&mut node, &attributes, scene_bundle);
     node
 }
 
+/// Markup of the text element being read, not of an element nested in it.
+fn current_markup<'m>(
+    text_markups: &'m mut [(node::NodeKey, String, MarkupBuilder)],
+    current_keys: &[node::NodeKey],
+) -> Option<&'m mut MarkupBuilder> {
+    match text_markups.last_mut() {
+        Some(&mut (text_key, _, ref mut markup)) if current_keys.last() == Some(&text_key) => Some(markup),
+        _ => None,
+    }
+}
+
+/// Styles the text of a `text` node with the spans of its markup. The clock replaces the text,
+/// so its markup is ignored.
+fn apply_text_markup(scene: &Scene, text_key: &node::NodeKey, element_name: &str, spans: Vec<TextSpan>) {
+    if spans.is_empty() || element_name == "clock" {
+        return;
+    }
+    if let Some(node) = scene.nodes.get(text_key) {
+        let mut node = node.borrow_mut();
+        if let Some(text_sprite) = node.sprite_mut::<text::TextSprite>() {
+            text_sprite.text = spans.iter().map(|span| span.text.as_str()).collect();
+            text_sprite.spans = spans;
+        }
+    }
+}
+
 fn process_text_attributes<'a>(
+    element_name: &str,
     attributes: Vec<xml::attribute::OwnedAttribute>,
     scene_bundle: &resource::SceneBundle,
+    fallback_fonts: &SharedFontList,
+    glyph_cache: &SharedGlyphCache,
 ) -> node::Node<'a> {
-    let visible = resolve_bool_from_attributes("visible", &attributes, true);
-    let tag = resolve_text_from_attributes("tag", &attributes, String::new());
     let alpha = resolve_float_from_attributes("alpha", &attributes, 1.0);
     let color =
         resolve_color_from_attributes(&attributes, color::GRAY).color_with_alpha_float(alpha);
     let pos = resolve_position_from_attributes(&attributes, FLOAT_POS_ZERO);
     let size = resolve_size_from_attributes(&attributes, FLOAT_SIZE_HALF);
-    let anchor_point = resolve_anchor_from_attributes(&attributes, ANCHOR_POINT_CENTER);
     let gravity = resolve_gravity_from_attributes(&attributes, GRAVITY_CENTER);
     let height = resolve_float_from_attributes("height", &attributes, 1.0);
     let text = resolve_text_from_attributes("text", &attributes, String::new());
     let font_filename = resolve_text_from_attributes("font", &attributes, String::new());
-    let clip_to_bounds = resolve_bool_from_attributes("clip_to_bounds", &attributes, false);
 
     let mut text_sprite = text::TextSprite::new();
     text_sprite.height = height;
     text_sprite.text = text;
     text_sprite.color = color;
     text_sprite.gravity = gravity;
+    text_sprite.font_size = attribute_by_name(&attributes, "font-size")
+        .map(|attribute| resolve_float_from_value(&attribute.value, 16.0));
+    text_sprite.wrap = resolve_bool_from_attributes("wrap", &attributes, false);
+    text_sprite.align = resolve_text_align_from_attributes(&attributes, text::TextAlign::Left);
+    text_sprite.line_spacing = resolve_float_from_attributes("line-spacing", &attributes, 1.0);
+    text_sprite.max_lines = attribute_by_name(&attributes, "max-lines")
+        .map(|attribute| resolve_float_from_value(&attribute.value, 1.0).max(1.0) as usize);
+    if resolve_bool_from_attributes("auto-fit", &attributes, false) {
+        text_sprite.auto_fit = Some(resolve_float_from_attributes("min-font-size", &attributes, 8.0));
+    }
+    text_sprite.ellipsis = resolve_text_ellipsis_from_attributes(&attributes);
+    text_sprite.outline = resolve_outline_from_attributes(&attributes);
+    text_sprite.shadow = resolve_shadow_from_attributes("text-shadow", &attributes);
+    text_sprite.underline = resolve_bool_from_attributes("underline", &attributes, false);
+    text_sprite.strikethrough = resolve_bool_from_attributes("strikethrough", &attributes, false);
+    text_sprite.background = resolve_named_color_from_attributes("background", &attributes);
+    text_sprite.background_padding = resolve_float_from_attributes("background-padding", &attributes, 0.0) as i32;
+    if element_name == "clock" {
+        let format = resolve_text_from_attributes("format", &attributes, String::from("%H:%M:%S"));
+        let timezone = resolve_text_from_attributes("timezone", &attributes, String::from("local"));
+        let time_zone = TimeZone::parse(&timezone).unwrap_or_else(|| {
+            println!("Unknown timezone {}", timezone);
+            TimeZone::Local
+        });
+        let clock = Clock::new(&format, time_zone);
+        text_sprite.text = clock.text();
+        text_sprite.clock = Some(clock);
+    }
 
     if font_filename.len() > 0 {
         let font_filename_path = scene_bundle.target_path().join(font_filename);
@@ -171,6 +324,9 @@ fn process_text_attributes<'a>(
             Err(e) => println!("{:?}", e),
         }
     }
+    text_sprite.fallback_fonts = resolve_fonts_from_attributes("fallback-fonts", &attributes, scene_bundle);
+    text_sprite.set_scene_fallback_fonts(fallback_fonts);
+    text_sprite.set_glyph_cache(glyph_cache);
 
     let mut node = node::Node::new_text_node(
         FloatRect {
@@ -179,48 +335,35 @@ fn process_text_attributes<'a>(
         },
         text_sprite,
     );
-    node.anchor_point = anchor_point;
-    node.tag = tag;
-    node.clip_to_bounds = clip_to_bounds;
-    node.visible = visible;
+    apply_node_attributes(&mut node, &attributes, scene_bundle);
     node
 }
 
 fn process_texture_attributes<'a>(
     attributes: Vec<xml::attribute::OwnedAttribute>,
     scene_bundle: &resource::SceneBundle,
+    texture_cache: &SharedTextureCache,
 ) -> node::Node<'a> {
-    let visible = resolve_bool_from_attributes("visible", &attributes, true);
-    let tag = resolve_text_from_attributes("tag", &attributes, String::new());
     let pos = resolve_position_from_attributes(&attributes, FLOAT_POS_ZERO);
     let size = resolve_size_from_attributes(&attributes, FLOAT_SIZE_HALF);
-    let anchor_point = resolve_anchor_from_attributes(&attributes, ANCHOR_POINT_CENTER);
     let gravity = resolve_gravity_from_attributes(&attributes, GRAVITY_CENTER);
     let texture_filename = resolve_text_from_attributes("image", &attributes, String::new());
-    let clip_to_bounds = resolve_bool_from_attributes("clip_to_bounds", &attributes, false);
-    let filter = resolve_text_from_attributes("filter", &attributes, String::from("triangle")).to_lowercase();
+    let filter = resolve_filter_from_attributes(&attributes, imageops::Triangle);
 
     let mut texture_sprite = TextureSprite::new();
-
+    texture_sprite.set_texture_cache(texture_cache);
     let texture_filename_path = scene_bundle.target_path().join(texture_filename);
     texture_sprite.set_texture_filename(texture_filename_path.into_os_string().into_string().unwrap().as_ref());
     texture_sprite.gravity = gravity;
-    texture_sprite.filter = {
-        if filter == "nearest" {
-            imageops::Nearest
-        } else if filter == "triangle" {
-            imageops::Triangle
-        } else if filter == "catmullRom" {
-            imageops::CatmullRom
-        } else if filter == "gaussian" {
-            imageops::Gaussian
-        } else if filter == "lanczos3" {
-            imageops::Lanczos3
-        } else {
-            imageops::Triangle
-        }
-    };
-
+    texture_sprite.filter = filter;
+    texture_sprite.fit = resolve_fit_from_attributes(&attributes, Fit::Contain);
+    texture_sprite.filters = resolve_image_filters_from_attributes(&attributes);
+    if let Some(attribute) = attribute_by_name(&attributes, "loop-count") {
+        texture_sprite.set_loop_count(resolve_float_from_value(&attribute.value, 0.0) as u32);
+    }
+    if !resolve_bool_from_attributes("autoplay", &attributes, true) {
+        texture_sprite.pause();
+    }
 
     let mut node = node::Node::new_texture_node(
         FloatRect {
@@ -229,30 +372,605 @@ fn process_texture_attributes<'a>(
         },
         texture_sprite,
     );
-    node.anchor_point = anchor_point;
-    node.tag = tag;
-    node.clip_to_bounds = clip_to_bounds;
-    node.visible = visible;
+    apply_node_attributes(&mut node, &attributes, scene_bundle);
+    node
+}
+
+fn process_shape_attributes<'a>(
+    element_name: &str,
+    attributes: Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
+) -> node::Node<'a> {
+    let alpha = resolve_float_from_attributes("alpha", &attributes, 1.0);
+    let pos = resolve_position_from_attributes(&attributes, FLOAT_POS_ZERO);
+    let size = resolve_size_from_attributes(&attributes, FLOAT_SIZE_HALF);
+    let view_box = resolve_size_from_value(
+        &resolve_text_from_attributes("view-box", &attributes, String::new()),
+        FLOAT_SIZE_FULL,
+    );
+    let fill = resolve_named_color_from_attributes("fill", &attributes)
+        .map(|color| multiply_alpha(color, alpha));
+    let stroke_color = resolve_named_color_from_attributes("stroke", &attributes);
+    let stroke_width = resolve_float_from_attributes("stroke-width", &attributes, 1.0);
+    let line_join = resolve_text_from_attributes("line-join", &attributes, String::from("miter")).to_lowercase();
+    let line_cap = resolve_text_from_attributes("line-cap", &attributes, String::from("butt")).to_lowercase();
+
+    let shape = if element_name == "line" {
+        shape::Shape::Line {
+            from: resolve_float_pos_from_value(&resolve_text_from_attributes("from", &attributes, String::new()), FLOAT_POS_ZERO),
+            to: resolve_float_pos_from_value(&resolve_text_from_attributes("to", &attributes, String::new()), FloatPos { x: view_box.width, y: view_box.height }),
+        }
+    } else if element_name == "ellipse" {
+        let center = resolve_float_pos_from_value(
+            &resolve_text_from_attributes("center", &attributes, String::new()),
+            FloatPos { x: view_box.width / 2.0, y: view_box.height / 2.0 },
+        );
+        let radius = resolve_size_from_value(
+            &resolve_text_from_attributes("radius", &attributes, String::new()),
+            FloatSize { width: view_box.width / 2.0, height: view_box.height / 2.0 },
+        );
+        match (attribute_by_name(&attributes, "start-angle"), attribute_by_name(&attributes, "end-angle")) {
+            (None, None) => shape::Shape::Ellipse { center, radius },
+            _ => shape::Shape::Arc {
+                center,
+                radius,
+                start_angle: resolve_float_from_attributes("start-angle", &attributes, 0.0),
+                end_angle: resolve_float_from_attributes("end-angle", &attributes, 360.0),
+            },
+        }
+    } else if element_name == "polyline" || element_name == "polygon" {
+        let values: Vec<f32> = resolve_text_from_attributes("points", &attributes, String::new())
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .map(|token| resolve_float_from_value(token, 0.0))
+            .collect();
+        let points = values
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| FloatPos { x: pair[0], y: pair[1] })
+            .collect();
+        if element_name == "polygon" {
+            shape::Shape::Polygon(points)
+        } else {
+            shape::Shape::Polyline(points)
+        }
+    } else {
+        shape::Shape::Path(resolve_text_from_attributes("d", &attributes, String::new()))
+    };
+
+    let mut shape_sprite = shape::ShapeSprite::new(shape);
+    shape_sprite.view_box = view_box;
+    shape_sprite.fill = fill;
+    // Outlines are drawn by default unless the shape is only filled.
+    shape_sprite.stroke = match stroke_color {
+        Some(stroke_color) => Some(stroke_color),
+        None if fill.is_none() => Some(color::BLACK),
+        None => None,
+    }.map(|stroke_color| {
+        let mut stroke = shape::Stroke::new(multiply_alpha(stroke_color, alpha), stroke_width);
+        stroke.join = if line_join == "round" {
+            shape::LineJoin::Round
+        } else if line_join == "bevel" {
+            shape::LineJoin::Bevel
+        } else {
+            shape::LineJoin::Miter
+        };
+        stroke.cap = if line_cap == "round" {
+            shape::LineCap::Round
+        } else if line_cap == "square" {
+            shape::LineCap::Square
+        } else {
+            shape::LineCap::Butt
+        };
+        stroke
+    });
+
+    let mut node = node::Node::new_shape_node(
+        FloatRect {
+            pos,
+            size,
+        },
+        shape_sprite,
+    );
+    apply_node_attributes(&mut node, &attributes, scene_bundle);
+    node
+}
+
+fn process_nine_patch_attributes<'a>(
+    attributes: Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
+) -> node::Node<'a> {
+    let pos = resolve_position_from_attributes(&attributes, FLOAT_POS_ZERO);
+    let size = resolve_size_from_attributes(&attributes, FLOAT_SIZE_HALF);
+    let image_filename = resolve_text_from_attributes("image", &attributes, String::new());
+    let filter = resolve_filter_from_attributes(&attributes, imageops::Triangle);
+
+    let mut nine_patch_sprite = NinePatchSprite::new();
+    let image_filename_path = scene_bundle.target_path().join(image_filename);
+    nine_patch_sprite.set_image_filename(image_filename_path.into_os_string().into_string().unwrap().as_ref());
+    // Explicit insets override the markers of a .9.png image.
+    if let Some(insets) = resolve_insets_from_attributes(&attributes) {
+        nine_patch_sprite.insets = insets;
+    }
+    nine_patch_sprite.filter = filter;
+
+    let mut node = node::Node::new_nine_patch_node(
+        FloatRect {
+            pos,
+            size,
+        },
+        nine_patch_sprite,
+    );
+    apply_node_attributes(&mut node, &attributes, scene_bundle);
+    node
+}
+
+fn process_sprite_sheet_attributes<'a>(
+    attributes: Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
+) -> node::Node<'a> {
+    let pos = resolve_position_from_attributes(&attributes, FLOAT_POS_ZERO);
+    let size = resolve_size_from_attributes(&attributes, FLOAT_SIZE_HALF);
+    let gravity = resolve_gravity_from_attributes(&attributes, GRAVITY_CENTER);
+    let image_filename = resolve_text_from_attributes("image", &attributes, String::new());
+    let atlas_filename = resolve_text_from_attributes("atlas", &attributes, String::new());
+    let columns = resolve_float_from_attributes("columns", &attributes, 1.0) as u32;
+    let rows = resolve_float_from_attributes("rows", &attributes, 1.0) as u32;
+    let frames = resolve_float_from_attributes("frames", &attributes, 0.0) as usize;
+
+    let mut sprite_sheet_sprite = SpriteSheetSprite::new();
+    let image_filename_path = scene_bundle.target_path().join(image_filename);
+    sprite_sheet_sprite.set_sheet_filename(image_filename_path.into_os_string().into_string().unwrap().as_ref());
+    if atlas_filename.is_empty() {
+        sprite_sheet_sprite.set_grid(columns, rows, if frames > 0 { Some(frames) } else { None });
+    } else {
+        let atlas_filename_path = scene_bundle.target_path().join(atlas_filename);
+        if let Err(e) = sprite_sheet_sprite.set_atlas_filename(atlas_filename_path.into_os_string().into_string().unwrap().as_ref()) {
+            println!("{}", e);
+        }
+    }
+    sprite_sheet_sprite.gravity = gravity;
+    sprite_sheet_sprite.filter = resolve_filter_from_attributes(&attributes, imageops::Triangle);
+    sprite_sheet_sprite.fps = resolve_float_from_attributes("fps", &attributes, 12.0);
+    sprite_sheet_sprite.mode = resolve_playback_mode_from_attributes(&attributes, PlaybackMode::Loop);
+
+    let mut node = node::Node::new_sprite_sheet_node(
+        FloatRect {
+            pos,
+            size,
+        },
+        sprite_sheet_sprite,
+    );
+    apply_node_attributes(&mut node, &attributes, scene_bundle);
+    node
+}
+
+fn process_code_attributes<'a>(
+    element_name: &str,
+    attributes: Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
+) -> node::Node<'a> {
+    let alpha = resolve_float_from_attributes("alpha", &attributes, 1.0);
+    let pos = resolve_position_from_attributes(&attributes, FLOAT_POS_ZERO);
+    let size = resolve_size_from_attributes(&attributes, FLOAT_SIZE_HALF);
+    let gravity = resolve_gravity_from_attributes(&attributes, GRAVITY_CENTER);
+    let data = resolve_text_from_attributes("data", &attributes, String::new());
+    let color = multiply_alpha(resolve_color_from_attributes(&attributes, color::BLACK), alpha);
+    let background = multiply_alpha(
+        resolve_named_color_from_attributes("background", &attributes).unwrap_or(color::WHITE),
+        alpha,
+    );
+    let quiet_zone = attribute_by_name(&attributes, "quiet-zone")
+        .map(|attribute| resolve_float_from_value(&attribute.value, 0.0).max(0.0) as u32);
+
+    let float_frame = FloatRect {
+        pos,
+        size,
+    };
+    let mut node = if element_name == "qrcode" {
+        let mut qr_code_sprite = QrCodeSprite::new();
+        qr_code_sprite.color = color;
+        qr_code_sprite.background = background;
+        qr_code_sprite.gravity = gravity;
+        if let Some(quiet_zone) = quiet_zone {
+            qr_code_sprite.quiet_zone = quiet_zone;
+        }
+        let ecc = resolve_text_from_attributes("ecc", &attributes, String::new()).to_uppercase();
+        let error_correction = if ecc == "L" {
+            ErrorCorrection::Low
+        } else if ecc == "Q" {
+            ErrorCorrection::Quartile
+        } else if ecc == "H" {
+            ErrorCorrection::High
+        } else {
+            ErrorCorrection::Medium
+        };
+        if let Err(e) = qr_code_sprite.set_error_correction(error_correction) {
+            println!("{}", e);
+        }
+        if let Err(e) = qr_code_sprite.set_data(&data) {
+            println!("{}", e);
+        }
+        node::Node::new_qr_code_node(float_frame, qr_code_sprite)
+    } else {
+        let symbology = resolve_text_from_attributes("type", &attributes, String::new()).to_lowercase();
+        let symbology = if symbology == "ean13" || symbology == "ean-13" {
+            Symbology::Ean13
+        } else {
+            Symbology::Code128
+        };
+        let mut barcode_sprite = BarcodeSprite::new(symbology);
+        barcode_sprite.color = color;
+        barcode_sprite.background = background;
+        barcode_sprite.gravity = gravity;
+        if let Some(quiet_zone) = quiet_zone {
+            barcode_sprite.quiet_zone = quiet_zone;
+        }
+        if let Err(e) = barcode_sprite.set_data(&data) {
+            println!("{}", e);
+        }
+        node::Node::new_barcode_node(float_frame, barcode_sprite)
+    };
+    apply_node_attributes(&mut node, &attributes, scene_bundle);
     node
 }
 
+fn process_chart_attributes<'a>(
+    attributes: Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
+) -> node::Node<'a> {
+    let alpha = resolve_float_from_attributes("alpha", &attributes, 1.0);
+    let pos = resolve_position_from_attributes(&attributes, FLOAT_POS_ZERO);
+    let size = resolve_size_from_attributes(&attributes, FLOAT_SIZE_HALF);
+    let kind = resolve_text_from_attributes("type", &attributes, String::new()).to_lowercase();
+    let kind = if kind == "area" {
+        ChartKind::Area
+    } else if kind == "bar" {
+        ChartKind::Bar
+    } else if kind == "sparkline" {
+        ChartKind::Sparkline
+    } else {
+        ChartKind::Line
+    };
+    let values: Vec<f32> = resolve_text_from_attributes("values", &attributes, String::new())
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|token| !token.is_empty())
+        .map(|token| resolve_float_from_value(token, 0.0))
+        .collect();
+    let capacity = resolve_float_from_attributes("capacity", &attributes, values.len().max(60) as f32) as usize;
+    let line_width = resolve_float_from_attributes("line-width", &attributes, 2.0);
+
+    let mut chart_sprite = ChartSprite::new(kind, capacity);
+    chart_sprite.samples().extend(&values);
+    chart_sprite.min = attribute_by_name(&attributes, "min").map(|attribute| resolve_float_from_value(&attribute.value, 0.0));
+    chart_sprite.max = attribute_by_name(&attributes, "max").map(|attribute| resolve_float_from_value(&attribute.value, 1.0));
+    chart_sprite.color = multiply_alpha(resolve_color_from_attributes(&attributes, color::BLACK), alpha);
+    chart_sprite.line_width = line_width;
+    chart_sprite.fill = resolve_named_color_from_attributes("fill", &attributes).map(|color| multiply_alpha(color, alpha));
+    chart_sprite.background = resolve_named_color_from_attributes("background", &attributes).map(|color| multiply_alpha(color, alpha));
+    chart_sprite.grid = resolve_named_color_from_attributes("grid", &attributes)
+        .map(|color| shape::Stroke::new(multiply_alpha(color, alpha), 1.0));
+    chart_sprite.grid_rows = resolve_float_from_attributes("grid-rows", &attributes, 4.0) as u32;
+    chart_sprite.grid_columns = resolve_float_from_attributes("grid-columns", &attributes, 0.0) as u32;
+    chart_sprite.axis = resolve_named_color_from_attributes("axis", &attributes)
+        .map(|color| shape::Stroke::new(multiply_alpha(color, alpha), 1.0));
+    chart_sprite.labels = resolve_named_color_from_attributes("labels", &attributes).map(|color| multiply_alpha(color, alpha));
+    chart_sprite.label_size = resolve_float_from_attributes("label-size", &attributes, 14.0);
+
+    let mut node = node::Node::new_chart_node(
+        FloatRect {
+            pos,
+            size,
+        },
+        chart_sprite,
+    );
+    apply_node_attributes(&mut node, &attributes, scene_bundle);
+    node
+}
+
+fn resolve_text_align_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+    default: text::TextAlign,
+) -> text::TextAlign {
+    let align = resolve_text_from_attributes("align", attributes, String::new()).to_lowercase();
+    if align == "left" {
+        text::TextAlign::Left
+    } else if align == "center" {
+        text::TextAlign::Center
+    } else if align == "right" {
+        text::TextAlign::Right
+    } else if align == "justify" {
+        text::TextAlign::Justify
+    } else {
+        default
+    }
+}
+
+fn resolve_text_ellipsis_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+) -> Option<text::TextEllipsis> {
+    let ellipsis = resolve_text_from_attributes("ellipsis", attributes, String::new()).to_lowercase();
+    if ellipsis == "start" {
+        Some(text::TextEllipsis::Start)
+    } else if ellipsis == "middle" {
+        Some(text::TextEllipsis::Middle)
+    } else if ellipsis == "end" {
+        Some(text::TextEllipsis::End)
+    } else {
+        None
+    }
+}
+
+/// Fonts of the bundle listed comma separated, fonts which fail to load are left out.
+fn resolve_fonts_from_attributes(
+    name: &str,
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
+) -> Vec<TextFont> {
+    let value = resolve_text_from_attributes(name, attributes, String::new());
+    let mut fonts = Vec::new();
+    for filename in value.split(',').map(|filename| filename.trim()).filter(|filename| !filename.is_empty()) {
+        let font = fs::File::open(scene_bundle.target_path().join(filename))
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|mut file| TextFont::from_file(&mut file));
+        match font {
+            Ok(font) => fonts.push(font),
+            Err(e) => println!("{}", e),
+        }
+    }
+    fonts
+}
+
+fn resolve_playback_mode_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+    default: PlaybackMode,
+) -> PlaybackMode {
+    let mode = resolve_text_from_attributes("mode", attributes, String::new()).to_lowercase();
+    if mode == "loop" {
+        PlaybackMode::Loop
+    } else if mode == "pingpong" || mode == "ping-pong" {
+        PlaybackMode::PingPong
+    } else if mode == "once" {
+        PlaybackMode::Once
+    } else {
+        default
+    }
+}
+
+/// Color filters from the tint, grayscale, sepia, brightness, contrast, saturation, invert and
+/// blur attributes, applied in that order.
+fn resolve_image_filters_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+) -> Vec<ImageFilter> {
+    let mut filters = Vec::new();
+    if let Some(color) = resolve_named_color_from_attributes("tint", attributes) {
+        filters.push(ImageFilter::Tint(color));
+    }
+    type AmountFilter = (&'static str, fn(f32) -> ImageFilter);
+    let amount_filters: [AmountFilter; 7] = [
+        ("grayscale", ImageFilter::Grayscale),
+        ("sepia", ImageFilter::Sepia),
+        ("brightness", ImageFilter::Brightness),
+        ("contrast", ImageFilter::Contrast),
+        ("saturation", ImageFilter::Saturation),
+        ("invert", ImageFilter::Invert),
+        ("blur", ImageFilter::Blur),
+    ];
+    for &(name, filter) in amount_filters.iter() {
+        if let Some(attribute) = attribute_by_name(attributes, name) {
+            filters.push(filter(resolve_float_from_value(&attribute.value, 0.0)));
+        }
+    }
+    filters
+}
+
+/// Mask from mask="ellipse", mask="rounded-rect" with corner-radius, or mask-image.
+fn resolve_mask_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+    scene_bundle: &resource::SceneBundle,
+) -> Option<Mask> {
+    let mask_image = resolve_text_from_attributes("mask-image", attributes, String::new());
+    if !mask_image.is_empty() {
+        let mask_image_path = scene_bundle.target_path().join(mask_image);
+        return match Mask::new_from_image_filename(mask_image_path.into_os_string().into_string().unwrap().as_ref()) {
+            Ok(mask) => Some(mask),
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        };
+    }
+    let mask = resolve_text_from_attributes("mask", attributes, String::new()).to_lowercase();
+    if mask == "ellipse" {
+        Some(Mask::new(MaskShape::Ellipse))
+    } else if mask == "rounded-rect" {
+        let corner_radius = resolve_float_from_attributes("corner-radius", attributes, 0.0);
+        Some(Mask::new(MaskShape::RoundedRect(corner_radius)))
+    } else {
+        if !mask.is_empty() {
+            println!("Wrong mask value ({}). Expected ellipse or rounded-rect.", mask);
+        }
+        None
+    }
+}
+
+fn resolve_fit_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+    default: Fit,
+) -> Fit {
+    let fit = resolve_text_from_attributes("fit", attributes, String::new()).to_lowercase();
+    if fit == "contain" {
+        Fit::Contain
+    } else if fit == "cover" {
+        Fit::Cover
+    } else if fit == "fill" {
+        Fit::Fill
+    } else if fit == "none" {
+        Fit::None
+    } else if fit == "tile" {
+        Fit::Tile
+    } else if fit == "scale-down" {
+        Fit::ScaleDown
+    } else {
+        default
+    }
+}
+
+fn resolve_filter_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+    default: imageops::FilterType,
+) -> imageops::FilterType {
+    let filter = resolve_text_from_attributes("filter", attributes, String::new()).to_lowercase();
+    if filter == "nearest" {
+        imageops::Nearest
+    } else if filter == "triangle" {
+        imageops::Triangle
+    } else if filter == "catmullrom" {
+        imageops::CatmullRom
+    } else if filter == "gaussian" {
+        imageops::Gaussian
+    } else if filter == "lanczos3" {
+        imageops::Lanczos3
+    } else {
+        default
+    }
+}
+
+/// Insets as "left top right bottom", "horizontal vertical" or a single value for all sides.
+fn resolve_insets_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+) -> Option<Insets> {
+    if let Some(attribute) = attribute_by_name(attributes, "insets") {
+        let values: Vec<i32> = attribute
+            .value
+            .split_whitespace()
+            .map(|token| resolve_float_from_value(token, 0.0) as i32)
+            .collect();
+        match values.len() {
+            1 => return Some(Insets { left: values[0], top: values[0], right: values[0], bottom: values[0] }),
+            2 => return Some(Insets { left: values[0], top: values[1], right: values[0], bottom: values[1] }),
+            4 => return Some(Insets { left: values[0], top: values[1], right: values[2], bottom: values[3] }),
+            _ => println!("Wrong insets value ({}). Expected \"left top right bottom\".", attribute.value),
+        }
+    }
+    None
+}
+
 fn resolve_color_from_attributes(
     attributes: &Vec<xml::attribute::OwnedAttribute>,
     default: Color,
 ) -> Color {
     if let Some(attribute) = attribute_by_name(attributes, "color") {
-        let value = &attribute.value;
-        if value.starts_with("#") {
-            if let Some(color) = color::color_by_hex(value.get(1..).unwrap()) {
-                return color;
+        if let Some(color) = resolve_color_from_value(&attribute.value) {
+            return color;
+        }
+    }
+    default
+}
+
+pub(crate) fn resolve_color_from_value(value: &str) -> Option<Color> {
+    if value.starts_with("#") {
+        color::color_by_hex(value.get(1..).unwrap())
+    } else {
+        color::color_by_name(value)
+    }
+}
+
+fn resolve_named_color_from_attributes(
+    name: &str,
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+) -> Option<Color> {
+    if let Some(attribute) = attribute_by_name(attributes, name) {
+        return resolve_color_from_value(&attribute.value);
+    }
+    None
+}
+
+fn multiply_alpha(color: Color, alpha: f32) -> Color {
+    let (_, _, _, color_alpha) = color.rgba();
+    color.color_with_alpha_float(color_alpha as f32 / 255.0 * alpha)
+}
+
+fn resolve_float_pos_from_value(value: &str, default: FloatPos) -> FloatPos {
+    let tokens: Vec<&str> = value.split_whitespace().collect();
+    if tokens.len() == 2 {
+        FloatPos {
+            x: resolve_float_from_value(tokens[0], default.x),
+            y: resolve_float_from_value(tokens[1], default.y),
+        }
+    } else {
+        default
+    }
+}
+
+fn resolve_size_from_value(value: &str, default: FloatSize) -> FloatSize {
+    let tokens: Vec<&str> = value.split_whitespace().collect();
+    if tokens.len() == 2 {
+        FloatSize {
+            width: resolve_float_from_value(tokens[0], default.width),
+            height: resolve_float_from_value(tokens[1], default.height),
+        }
+    } else if tokens.len() == 1 {
+        FloatSize {
+            width: resolve_float_from_value(tokens[0], default.width),
+            height: resolve_float_from_value(tokens[0], default.height),
+        }
+    } else {
+        default
+    }
+}
+
+fn resolve_shadow_from_attributes(
+    name: &str,
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+) -> Option<Shadow> {
+    if let Some(attribute) = attribute_by_name(attributes, name) {
+        let tokens: Vec<&str> = attribute.value.split_whitespace().collect();
+        if tokens.len() == 4 {
+            if let Some(color) = resolve_color_from_value(tokens[3]) {
+                return Some(Shadow::new(
+                    Pos {
+                        x: resolve_float_from_value(tokens[0], 0.0) as i32,
+                        y: resolve_float_from_value(tokens[1], 0.0) as i32,
+                    },
+                    resolve_float_from_value(tokens[2], 0.0) as i32,
+                    color,
+                ));
             }
-        } else {
-            if let Some(color) = color::color_by_name(&value) {
-                return color;
+        }
+        println!("Wrong {} value ({}). Expected \"x y blur color\".", name, attribute.value);
+    }
+    None
+}
+
+fn resolve_glow_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+) -> Option<Glow> {
+    if let Some(attribute) = attribute_by_name(attributes, "glow") {
+        let tokens: Vec<&str> = attribute.value.split_whitespace().collect();
+        if tokens.len() == 2 {
+            if let Some(color) = resolve_color_from_value(tokens[1]) {
+                return Some(Glow::new(resolve_float_from_value(tokens[0], 0.0) as i32, color));
             }
         }
+        println!("Wrong glow value ({}). Expected \"radius color\".", attribute.value);
     }
-    default
+    None
+}
+
+fn resolve_outline_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+) -> Option<text::TextOutline> {
+    if let Some(attribute) = attribute_by_name(attributes, "outline") {
+        let tokens: Vec<&str> = attribute.value.split_whitespace().collect();
+        if tokens.len() == 2 {
+            if let Some(color) = resolve_color_from_value(tokens[1]) {
+                return Some(text::TextOutline::new(resolve_float_from_value(tokens[0], 0.0), color));
+            }
+        }
+        println!("Wrong outline value ({}). Expected \"width color\".", attribute.value);
+    }
+    None
 }
 
 fn resolve_position_from_attributes(
@@ -343,6 +1061,50 @@ fn resolve_gravity_from_attributes(
     default
 }
 
+fn resolve_scale_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+    default: Scale,
+) -> Scale {
+    if let Some(attribute) = attribute_by_name(attributes, "scale") {
+        let value = &attribute.value;
+        let tokens: Vec<&str> = value.split_whitespace().collect();
+        if tokens.len() == 2 {
+            return Scale {
+                x: resolve_float_from_value(tokens[0], default.x),
+                y: resolve_float_from_value(tokens[1], default.y),
+            };
+        } else if tokens.len() == 1 {
+            return Scale {
+                x: resolve_float_from_value(tokens[0], default.x),
+                y: resolve_float_from_value(tokens[0], default.y),
+            };
+        }
+    }
+    default
+}
+
+fn resolve_skew_from_attributes(
+    attributes: &Vec<xml::attribute::OwnedAttribute>,
+    default: Skew,
+) -> Skew {
+    if let Some(attribute) = attribute_by_name(attributes, "skew") {
+        let value = &attribute.value;
+        let tokens: Vec<&str> = value.split_whitespace().collect();
+        if tokens.len() == 2 {
+            return Skew {
+                x: resolve_float_from_value(tokens[0], default.x),
+                y: resolve_float_from_value(tokens[1], default.y),
+            };
+        } else if tokens.len() == 1 {
+            return Skew {
+                x: resolve_float_from_value(tokens[0], default.x),
+                y: default.y,
+            };
+        }
+    }
+    default
+}
+
 fn resolve_float_from_attributes(
     name: &str,
     attributes: &Vec<xml::attribute::OwnedAttribute>,
@@ -402,3 +1164,34 @@ fn attribute_by_name<'a>(
         .iter()
         .find(|attribute| attribute.name.local_name == name)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    fn scene_from_xml<'a>(xml: &str) -> Scene<'a> {
+        let dir = tempdir::TempDir::new("scene").unwrap();
+        let mut file = fs::File::create(dir.path().join("scene.xml")).unwrap();
+        file.write_all(xml.as_bytes()).unwrap();
+        Scene::new_from_bundle(dir.path().to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn text_keeps_markup_around_child_nodes() {
+        let scene = scene_from_xml(
+            r#"<scene><text text="plain">Before <b>bold</b><box tag="child"/> after</text></scene>"#,
+        );
+        let (text_key, text) = scene
+            .nodes
+            .iter()
+            .filter_map(|(key, node)| node.borrow().sprite::<text::TextSprite>().map(|sprite| (*key, sprite.text.clone())))
+            .next()
+            .unwrap();
+        assert_eq!(text, "Before bold after");
+        let children = scene.hierarchy[&text_key].borrow();
+        assert_eq!(children.len(), 1);
+        assert_eq!(scene.nodes[&children[0]].borrow().tag, "child");
+    }
+}
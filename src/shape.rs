@@ -1,8 +1,11 @@
 
+extern crate tiny_skia;
+
 use screen_writer::{ScreenInfo};
 use color;
-use dimension::{Rect, Size, RECT_ZERO, POS_ZERO};
-use sprite::{Sprite, render_to_canvas};
+use dimension::{FloatPos, FloatSize, Rect, FLOAT_SIZE_FULL, RECT_ZERO, POS_ZERO};
+use path;
+use sprite::{Sprite, pixels_from_pixmap};
 
 pub struct RectSprite {
     pub color: color::Color,
@@ -23,16 +26,200 @@ impl RectSprite {
 impl<'a> Sprite<'a> for RectSprite {
 
     fn draw(&mut self, fixed_rect:&Rect, _screen_info:&ScreenInfo) {
-        self.rect = *fixed_rect;
+        self.rect = Rect { pos: POS_ZERO, size: fixed_rect.size };
         let size = (self.rect.size.width * self.rect.size.height) as usize;
         let color::Color(c) = self.color;
         self.raw_pixels = vec![c; size];
     }
 
-    fn render(&mut self, parent_node_frame:&Rect, node_frame:&Rect, clip_to_bounds:bool, screen_info:&ScreenInfo, canvas_ptr:*mut u32) {
-        let src_slice_ptr_u32 = self.raw_pixels.as_ptr() as *mut u32;
-        let inner_frame = Rect { pos: POS_ZERO, size: Size { width: self.rect.size.width, height : self.rect.size.height}};
+    fn pixels(&self) -> (&[u32], &Rect) {
+        (&self.raw_pixels, &self.rect)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Shape {
+    Line { from: FloatPos, to: FloatPos },
+    Polyline(Vec<FloatPos>),
+    Polygon(Vec<FloatPos>),
+    Ellipse { center: FloatPos, radius: FloatSize },
+    /// Open elliptical arc, angles in degrees clockwise from the positive x axis.
+    Arc { center: FloatPos, radius: FloatSize, start_angle: f32, end_angle: f32 },
+    /// SVG path data, e.g. `M 0 0 L 10 10 Z`.
+    Path(String),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum LineJoin {
+    Miter,
+    Round,
+    Bevel,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum LineCap {
+    Butt,
+    Round,
+    Square,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Stroke {
+    pub color: color::Color,
+    /// Width in pixels, not affected by the view box scale.
+    pub width: f32,
+    pub join: LineJoin,
+    pub cap: LineCap,
+}
 
-        render_to_canvas(src_slice_ptr_u32, parent_node_frame,node_frame, &inner_frame,clip_to_bounds, screen_info, canvas_ptr);
+impl Stroke {
+    pub fn new(color: color::Color, width: f32) -> Stroke {
+        Stroke {
+            color,
+            width,
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+        }
     }
 }
+
+/// Anti-aliased vector shape. Shape coordinates are in `view_box` units which are
+/// stretched over the node frame, by default `1 x 1` so coordinates are fractions of the frame.
+pub struct ShapeSprite {
+    pub shape: Shape,
+    pub fill: Option<color::Color>,
+    pub stroke: Option<Stroke>,
+    pub view_box: FloatSize,
+    frame: Rect,
+    raw_pixels: Vec<u32>,
+}
+
+impl ShapeSprite {
+    pub fn new(shape: Shape) -> ShapeSprite {
+        ShapeSprite {
+            shape,
+            fill: None,
+            stroke: Some(Stroke::new(color::BLACK, 1.0)),
+            view_box: FLOAT_SIZE_FULL,
+            frame: RECT_ZERO,
+            raw_pixels: Vec::new(),
+        }
+    }
+
+    fn build_path(&self) -> Result<tiny_skia::Path, String> {
+        let mut builder = tiny_skia::PathBuilder::new();
+        match self.shape {
+            Shape::Line { from, to } => {
+                builder.move_to(from.x, from.y);
+                builder.line_to(to.x, to.y);
+            }
+            Shape::Polyline(ref points) | Shape::Polygon(ref points) => {
+                for (index, point) in points.iter().enumerate() {
+                    if index == 0 {
+                        builder.move_to(point.x, point.y);
+                    } else {
+                        builder.line_to(point.x, point.y);
+                    }
+                }
+                if let Shape::Polygon(_) = self.shape {
+                    builder.close();
+                }
+            }
+            Shape::Ellipse { center, radius } => {
+                if let Some(oval) = tiny_skia::Rect::from_xywh(
+                    center.x - radius.width,
+                    center.y - radius.height,
+                    radius.width * 2.0,
+                    radius.height * 2.0,
+                ) {
+                    builder.push_oval(oval);
+                }
+            }
+            Shape::Arc { center, radius, start_angle, end_angle } => {
+                let start = start_angle.to_radians();
+                let (sin, cos) = start.sin_cos();
+                builder.move_to(center.x + radius.width * cos, center.y + radius.height * sin);
+                path::append_arc(
+                    &mut builder,
+                    (center.x, center.y),
+                    (radius.width, radius.height),
+                    0.0,
+                    start,
+                    (end_angle - start_angle).to_radians(),
+                );
+            }
+            Shape::Path(ref data) => return path::parse_path_data(data),
+        }
+        builder.finish().ok_or_else(|| String::from("Shape is empty"))
+    }
+}
+
+impl<'a> Sprite<'a> for ShapeSprite {
+    fn draw(&mut self, fixed_rect: &Rect, _screen_info: &ScreenInfo) {
+        self.frame = Rect { pos: POS_ZERO, size: fixed_rect.size };
+        self.raw_pixels.clear();
+
+        let mut pixmap = match tiny_skia::Pixmap::new(fixed_rect.size.width as u32, fixed_rect.size.height as u32) {
+            Some(pixmap) => pixmap,
+            None => {
+                self.frame = RECT_ZERO;
+                return;
+            }
+        };
+
+        let path = match self.build_path() {
+            Ok(path) => path,
+            Err(e) => {
+                println!("{}", e);
+                self.raw_pixels = vec![0; pixmap.pixels().len()];
+                return;
+            }
+        };
+        // The path is scaled up front so the stroke width stays in pixels.
+        let view_box_transform = tiny_skia::Transform::from_scale(
+            fixed_rect.size.width as f32 / self.view_box.width,
+            fixed_rect.size.height as f32 / self.view_box.height,
+        );
+        if let Some(path) = path.transform(view_box_transform) {
+            if let Some(fill) = self.fill {
+                let paint = paint_for_color(fill);
+                pixmap.fill_path(&path, &paint, tiny_skia::FillRule::Winding, tiny_skia::Transform::identity(), None);
+            }
+            if let Some(ref stroke) = self.stroke {
+                let paint = paint_for_color(stroke.color);
+                let skia_stroke = skia_stroke(stroke);
+                pixmap.stroke_path(&path, &paint, &skia_stroke, tiny_skia::Transform::identity(), None);
+            }
+        }
+        self.raw_pixels = pixels_from_pixmap(&pixmap);
+    }
+
+    fn pixels(&self) -> (&[u32], &Rect) {
+        (&self.raw_pixels, &self.frame)
+    }
+}
+
+pub fn skia_stroke(stroke: &Stroke) -> tiny_skia::Stroke {
+    tiny_skia::Stroke {
+        width: stroke.width,
+        line_join: match stroke.join {
+            LineJoin::Miter => tiny_skia::LineJoin::Miter,
+            LineJoin::Round => tiny_skia::LineJoin::Round,
+            LineJoin::Bevel => tiny_skia::LineJoin::Bevel,
+        },
+        line_cap: match stroke.cap {
+            LineCap::Butt => tiny_skia::LineCap::Butt,
+            LineCap::Round => tiny_skia::LineCap::Round,
+            LineCap::Square => tiny_skia::LineCap::Square,
+        },
+        ..tiny_skia::Stroke::default()
+    }
+}
+
+pub fn paint_for_color<'p>(color: color::Color) -> tiny_skia::Paint<'p> {
+    let (r, g, b, a) = color.rgba();
+    let mut paint = tiny_skia::Paint::default();
+    paint.set_color_rgba8(r, g, b, a);
+    paint.anti_alias = true;
+    paint
+}
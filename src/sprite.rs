@@ -1,34 +1,191 @@
-use screen_writer::{ScreenInfo, PIXEL_RGBA};
-use dimension::Rect;
+extern crate tiny_skia;
 
-pub trait Sprite<'a> {
+use std::any::{type_name, Any};
+use std::slice;
+use std::time;
+
+use screen_writer::{ScreenInfo, PixelDef, PIXEL_RGBA};
+use dimension::{Pos, Rect, Size};
+use mask::{apply_coverage, coverage, MaskClip};
+use transform::Transform;
+
+/// Content of a node. A sprite draws itself at the laid-out node size in `draw` and hands the
+/// result to the compositor from `pixels`; the scene calls `draw` again only when the node is
+/// laid out anew or `update` asks for it.
+///
+/// ```
+/// use fb2d::{Canvas, Color, Node, Rect, ScreenInfo, Sprite, FLOAT_RECT_FULL};
+///
+/// struct Bar {
+///     level: f32,
+///     canvas: Canvas,
+/// }
+///
+/// impl<'a> Sprite<'a> for Bar {
+///     fn draw(&mut self, outer_rect: &Rect, _screen_info: &ScreenInfo) {
+///         self.canvas.resize(outer_rect.size.width, outer_rect.size.height);
+///         let mut filled = *self.canvas.frame();
+///         filled.size.width = (filled.size.width as f32 * self.level) as i32;
+///         self.canvas.fill_rect(&filled, Color::from_rgba(0, 200, 0, 255));
+///     }
+///
+///     fn pixels(&self) -> (&[u32], &Rect) {
+///         (self.canvas.pixels(), self.canvas.frame())
+///     }
+/// }
+///
+/// let mut node = Node::new_with_sprite(FLOAT_RECT_FULL, Bar { level: 0.5, canvas: Canvas::new(0, 0) });
+/// if let Some(bar) = node.sprite_mut::<Bar>() {
+///     bar.level = 0.75;
+/// }
+/// ```
+pub trait Sprite<'a>: AsAny {
+    /// Draws the sprite for the node frame `fixed_rect`.
     fn draw(&mut self, fixed_rect: &Rect, screen_info: &ScreenInfo);
-    fn render(
-        &mut self,
-        parent_node_frame: &Rect,
-        node_frame: &Rect,
-        clip_to_bounds: bool,
-        screen_info: &ScreenInfo,
-        canvas_ptr: *mut u32,
-    );
+    /// Drawn pixels, straight alpha RGBA in the layout of `Color`, and the area they cover
+    /// relative to the node frame. Sprites with fewer pixels than the area holds are not drawn.
+    fn pixels(&self) -> (&[u32], &Rect);
+
+    /// Advances time based content to `clock`, the time elapsed since the scene started
+    /// running. Returns true when the sprite has to be drawn again.
+    fn update(&mut self, _clock: time::Duration) -> bool {
+        false
+    }
+
+    /// Short type name shown by the debug overlay.
+    fn type_name(&self) -> &'static str {
+        let type_name = type_name::<Self>();
+        type_name.rsplit("::").next().unwrap_or(type_name)
+    }
+}
+
+/// Gives access to the concrete sprite type behind `dyn Sprite`, implemented for every
+/// `'static` type.
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 impl<'a> Sprite<'a> {}
+
+pub fn pixels_from_rgba(raw_bytes: &[u8]) -> Vec<u32> {
+    raw_bytes
+        .chunks(4)
+        .map(|rgba| {
+            ((rgba[0] as u32) << PIXEL_RGBA.red_offset)
+                | ((rgba[1] as u32) << PIXEL_RGBA.green_offset)
+                | ((rgba[2] as u32) << PIXEL_RGBA.blue_offset)
+                | ((rgba[3] as u32) << PIXEL_RGBA.transp_offset)
+        })
+        .collect()
+}
+
+/// Converts a premultiplied tiny-skia pixmap to sprite pixels.
+pub fn pixels_from_pixmap(pixmap: &tiny_skia::Pixmap) -> Vec<u32> {
+    pixmap
+        .pixels()
+        .iter()
+        .map(|pixel| {
+            let pixel = pixel.demultiply();
+            ((pixel.red() as u32) << PIXEL_RGBA.red_offset)
+                | ((pixel.green() as u32) << PIXEL_RGBA.green_offset)
+                | ((pixel.blue() as u32) << PIXEL_RGBA.blue_offset)
+                | ((pixel.alpha() as u32) << PIXEL_RGBA.transp_offset)
+        })
+        .collect()
+}
+
+/// Sprite or effect pixels of a node together with the frames positioning them on the canvas.
+/// The pixels are borrowed from the node, so an item is only valid until the node draws again.
+#[derive(Debug, Clone)]
+pub struct RenderItem {
+    pub raw_pixels_ptr: *const u32,
+    pub parent_node_frame: Rect,
+    /// Maps `parent_node_frame` onto the screen.
+    pub parent_transform: Transform,
+    pub outer_frame: Rect,
+    pub inner_frame: Rect,
+    pub transform: Transform,
+    pub clip_to_bounds: bool,
+    /// Masks of the node and its ancestors, empty when not masked.
+    pub masks: Vec<MaskClip>,
+}
+
+// Items only read pixels which are left untouched while the canvas is composited.
+unsafe impl Send for RenderItem {}
+unsafe impl Sync for RenderItem {}
+
+impl RenderItem {
+    pub fn is_transformed(&self) -> bool {
+        !self.transform.is_identity() || !self.parent_transform.is_identity()
+    }
+
+    /// Canvas area the item may touch, including the debug frame.
+    pub fn bounds(&self) -> Rect {
+        let inner_rect = Rect {
+            pos: Pos {
+                x: self.outer_frame.pos.x + self.inner_frame.pos.x,
+                y: self.outer_frame.pos.y + self.inner_frame.pos.y,
+            },
+            size: self.inner_frame.size,
+        };
+        let left = inner_rect.pos.x.min(self.outer_frame.pos.x);
+        let top = inner_rect.pos.y.min(self.outer_frame.pos.y);
+        let right = (inner_rect.pos.x + inner_rect.size.width).max(self.outer_frame.pos.x + self.outer_frame.size.width);
+        let bottom = (inner_rect.pos.y + inner_rect.size.height).max(self.outer_frame.pos.y + self.outer_frame.size.height);
+        let rect = Rect {
+            pos: Pos { x: left, y: top },
+            size: Size { width: right - left, height: bottom - top },
+        };
+        if self.is_transformed() {
+            let mut bounds = self.transform.bounding_rect(&rect);
+            bounds.size.width += 1;
+            bounds.size.height += 1;
+            bounds
+        } else {
+            rect
+        }
+    }
+}
+
+pub fn intersects(rect: &Rect, other: &Rect) -> bool {
+    rect.pos.x < other.pos.x + other.size.width
+        && other.pos.x < rect.pos.x + rect.size.width
+        && rect.pos.y < other.pos.y + other.size.height
+        && other.pos.y < rect.pos.y + rect.size.height
+}
+
+/// Blends the item onto the canvas. Only canvas pixels inside `region` are touched.
 pub fn render_to_canvas(
-    raw_pixels_ptr: *const u32,
-    parent_node_frame: &Rect,
-    outer_frame: &Rect,
-    inner_frame: &Rect,
-    clip_to_bounds: bool,
+    item: &RenderItem,
+    region: &Rect,
     screen_info: &ScreenInfo,
     canvas_ptr: *mut u32,
 ) {
+    let raw_pixels_ptr = item.raw_pixels_ptr;
+    let parent_node_frame = &item.parent_node_frame;
+    let outer_frame = &item.outer_frame;
+    let inner_frame = &item.inner_frame;
+    let clip_to_bounds = item.clip_to_bounds;
+    let region_bottom = (region.pos.y + region.size.height).min(screen_info.yres);
+    let region_right = (region.pos.x + region.size.width).min(screen_info.xres);
+
     let outer_offset = outer_frame.pos.y * screen_info.xres + outer_frame.pos.x;
     let inner_offset = outer_offset + inner_frame.pos.y * screen_info.xres + inner_frame.pos.x;
 
     for y in 0..inner_frame.size.height {
         let effective_y = y + outer_frame.pos.y + inner_frame.pos.y;
-        let inside_screen_y = effective_y >= 0 && effective_y < screen_info.yres;
+        let inside_screen_y = effective_y >= region.pos.y.max(0) && effective_y < region_bottom;
         let inside_bounds_y = y + inner_frame.pos.y >= 0
             && y + inner_frame.pos.y < outer_frame.size.height
             && (effective_y >= parent_node_frame.pos.y
@@ -37,7 +194,7 @@ pub fn render_to_canvas(
         if inside_screen_y && ((clip_to_bounds && inside_bounds_y) || !clip_to_bounds) {
             for x in 0..inner_frame.size.width {
                 let effective_x = x + outer_frame.pos.x + inner_frame.pos.x;
-                let inside_screen_x = effective_x >= 0 && effective_x < screen_info.xres;
+                let inside_screen_x = effective_x >= region.pos.x.max(0) && effective_x < region_right;
                 let inside_bounds_x = x + inner_frame.pos.x >= 0
                     && x + inner_frame.pos.x < outer_frame.size.width
                     && (effective_x >= parent_node_frame.pos.x
@@ -47,46 +204,15 @@ pub fn render_to_canvas(
                     let dst_offset = inner_offset + y * screen_info.xres + x;
                     let src_offset = y * inner_frame.size.width + x;
                     unsafe {
-                        let dst_pixel = *canvas_ptr.offset(dst_offset as isize);
-                        let dst_r = ((dst_pixel >> screen_info.pixel_def.red_offset) & 0xFF) as f32;
-                        let dst_g =
-                            ((dst_pixel >> screen_info.pixel_def.green_offset) & 0xFF) as f32;
-                        let dst_b =
-                            ((dst_pixel >> screen_info.pixel_def.blue_offset) & 0xFF) as f32;
-
-                        let src_pixel = *raw_pixels_ptr.offset(src_offset as isize); // src_slice_u32[src_offset];
-
-                        let src_r = (src_pixel >> PIXEL_RGBA.red_offset) & 0xFF;
-                        let src_g = (src_pixel >> PIXEL_RGBA.green_offset) & 0xFF;
-                        let src_b = (src_pixel >> PIXEL_RGBA.blue_offset) & 0xFF;
+                        let mut src_pixel = *raw_pixels_ptr.offset(src_offset as isize); // src_slice_u32[src_offset];
+                        if !item.masks.is_empty() {
+                            src_pixel = apply_coverage(src_pixel, coverage(&item.masks, effective_x, effective_y));
+                        }
                         let src_a = (src_pixel >> PIXEL_RGBA.transp_offset) & 0xFF;
 
-                        if src_a as u32 == 0xFF {
-                            let new_pixel = (src_r << screen_info.pixel_def.red_offset)
-                                | (src_g << screen_info.pixel_def.green_offset)
-                                | (src_b << screen_info.pixel_def.blue_offset)
-                                | (0xFF << screen_info.pixel_def.transp_offset);
-
-                            *canvas_ptr.offset(dst_offset as isize) = new_pixel;
-                        } else if src_a as u32 != 0 {
-                            let src_r = src_r as f32;
-                            let src_g = src_g as f32;
-                            let src_b = src_b as f32;
-                            let src_a = src_a as f32;
-
-                            let right_a = src_a / 0xFF as f32;
-                            let left_a = 1.0 - right_a;
-
-                            let r = src_r * right_a + dst_r * left_a;
-                            let g = src_g * right_a + dst_g * left_a;
-                            let b = src_b * right_a + dst_b * left_a;
-
-                            let new_pixel = ((r as u32) << screen_info.pixel_def.red_offset)
-                                | ((g as u32) << screen_info.pixel_def.green_offset)
-                                | ((b as u32) << screen_info.pixel_def.blue_offset)
-                                | (0xFF << screen_info.pixel_def.transp_offset);
-
-                            *canvas_ptr.offset(dst_offset as isize) = new_pixel;
+                        if src_a != 0 {
+                            let dst_pixel = *canvas_ptr.offset(dst_offset as isize);
+                            *canvas_ptr.offset(dst_offset as isize) = blend_pixel(dst_pixel, src_pixel, &screen_info.pixel_def);
                         } else if screen_info.show_debug_info
                             && (x == 0 || y == 0 || x == inner_frame.size.width - 1
                                 || y == inner_frame.size.height - 1)
@@ -102,10 +228,10 @@ pub fn render_to_canvas(
     if screen_info.show_debug_info {
         for y in 0..outer_frame.size.height {
             let effective_y = y + outer_frame.pos.y;
-            if effective_y >= 0 && effective_y < screen_info.yres {
+            if effective_y >= region.pos.y.max(0) && effective_y < region_bottom {
                 for x in 0..outer_frame.size.width {
                     let effective_x = x + outer_frame.pos.x;
-                    if effective_x >= 0 && effective_x < screen_info.xres {
+                    if effective_x >= region.pos.x.max(0) && effective_x < region_right {
                         if x == 0 || y == 0 || x == outer_frame.size.width - 1
                             || y == outer_frame.size.height - 1
                         {
@@ -120,3 +246,158 @@ pub fn render_to_canvas(
         }
     }
 }
+
+/// Blends an RGBA source pixel over a destination pixel in the screen pixel format.
+pub fn blend_pixel(dst_pixel: u32, src_pixel: u32, pixel_def: &PixelDef) -> u32 {
+    let src_r = (src_pixel >> PIXEL_RGBA.red_offset) & 0xFF;
+    let src_g = (src_pixel >> PIXEL_RGBA.green_offset) & 0xFF;
+    let src_b = (src_pixel >> PIXEL_RGBA.blue_offset) & 0xFF;
+    let src_a = (src_pixel >> PIXEL_RGBA.transp_offset) & 0xFF;
+
+    if src_a == 0xFF {
+        (src_r << pixel_def.red_offset)
+            | (src_g << pixel_def.green_offset)
+            | (src_b << pixel_def.blue_offset)
+            | (0xFF << pixel_def.transp_offset)
+    } else if src_a != 0 {
+        let dst_r = ((dst_pixel >> pixel_def.red_offset) & 0xFF) as f32;
+        let dst_g = ((dst_pixel >> pixel_def.green_offset) & 0xFF) as f32;
+        let dst_b = ((dst_pixel >> pixel_def.blue_offset) & 0xFF) as f32;
+
+        let right_a = src_a as f32 / 0xFF as f32;
+        let left_a = 1.0 - right_a;
+
+        let r = src_r as f32 * right_a + dst_r * left_a;
+        let g = src_g as f32 * right_a + dst_g * left_a;
+        let b = src_b as f32 * right_a + dst_b * left_a;
+
+        ((r as u32) << pixel_def.red_offset)
+            | ((g as u32) << pixel_def.green_offset)
+            | ((b as u32) << pixel_def.blue_offset)
+            | (0xFF << pixel_def.transp_offset)
+    } else {
+        dst_pixel
+    }
+}
+
+/// Same as `render_to_canvas` for nodes with a non identity transform. Every canvas pixel
+/// covered by the transformed sprite is mapped back to the sprite and sampled bilinearly.
+pub fn render_to_canvas_transformed(
+    item: &RenderItem,
+    region: &Rect,
+    screen_info: &ScreenInfo,
+    canvas_ptr: *mut u32,
+) {
+    let raw_pixels_ptr = item.raw_pixels_ptr;
+    let parent_node_frame = &item.parent_node_frame;
+    let outer_frame = &item.outer_frame;
+    let inner_frame = &item.inner_frame;
+    let transform = &item.transform;
+    let parent_transform = &item.parent_transform;
+    let clip_to_bounds = item.clip_to_bounds;
+    let (inverse, parent_inverse) = match (transform.invert(), parent_transform.invert()) {
+        (Some(inverse), Some(parent_inverse)) => (inverse, parent_inverse),
+        _ => return,
+    };
+    if inner_frame.size.width <= 0 || inner_frame.size.height <= 0 {
+        return;
+    }
+
+    let raw_pixels = unsafe {
+        slice::from_raw_parts(raw_pixels_ptr, (inner_frame.size.width * inner_frame.size.height) as usize)
+    };
+    let source_rect = Rect {
+        pos: Pos {
+            x: outer_frame.pos.x + inner_frame.pos.x,
+            y: outer_frame.pos.y + inner_frame.pos.y,
+        },
+        size: inner_frame.size,
+    };
+    let source_x = source_rect.pos.x as f32;
+    let source_y = source_rect.pos.y as f32;
+    let mut bounds = transform.bounding_rect(&source_rect);
+    let max_x = (bounds.pos.x + bounds.size.width + 1).min(screen_info.xres).min(region.pos.x + region.size.width);
+    let max_y = (bounds.pos.y + bounds.size.height + 1).min(screen_info.yres).min(region.pos.y + region.size.height);
+    bounds.pos.x = bounds.pos.x.max(0).max(region.pos.x);
+    bounds.pos.y = bounds.pos.y.max(0).max(region.pos.y);
+
+    for y in bounds.pos.y..max_y {
+        for x in bounds.pos.x..max_x {
+            let (layout_x, layout_y) = inverse.apply(x as f32 + 0.5, y as f32 + 0.5);
+
+            if clip_to_bounds {
+                let inside_bounds = layout_x >= outer_frame.pos.x as f32
+                    && layout_x < (outer_frame.pos.x + outer_frame.size.width) as f32
+                    && layout_y >= outer_frame.pos.y as f32
+                    && layout_y < (outer_frame.pos.y + outer_frame.size.height) as f32;
+                let (parent_x, parent_y) = parent_inverse.apply(x as f32 + 0.5, y as f32 + 0.5);
+                let inside_parent = parent_x >= parent_node_frame.pos.x as f32
+                    && parent_x < (parent_node_frame.pos.x + parent_node_frame.size.width) as f32
+                    && parent_y >= parent_node_frame.pos.y as f32
+                    && parent_y < (parent_node_frame.pos.y + parent_node_frame.size.height) as f32;
+                if !inside_bounds || !inside_parent {
+                    continue;
+                }
+            }
+
+            let mut src_pixel = sample_bilinear(
+                raw_pixels,
+                &inner_frame.size,
+                layout_x - source_x - 0.5,
+                layout_y - source_y - 0.5,
+            );
+            if !item.masks.is_empty() {
+                src_pixel = apply_coverage(src_pixel, coverage(&item.masks, x, y));
+            }
+            if (src_pixel >> PIXEL_RGBA.transp_offset) & 0xFF != 0 {
+                let dst_offset = (y * screen_info.xres + x) as isize;
+                unsafe {
+                    let dst_pixel = *canvas_ptr.offset(dst_offset);
+                    *canvas_ptr.offset(dst_offset) = blend_pixel(dst_pixel, src_pixel, &screen_info.pixel_def);
+                }
+            }
+        }
+    }
+}
+
+fn sample_bilinear(raw_pixels: &[u32], size: &Size, u: f32, v: f32) -> u32 {
+    let x0 = u.floor();
+    let y0 = v.floor();
+    let fx = u - x0;
+    let fy = v - y0;
+    let x0 = x0 as i32;
+    let y0 = y0 as i32;
+
+    // Channels are interpolated premultiplied so transparent neighbours don't darken the edges.
+    let mut channels = [0f32; 4];
+    for &(dx, dy, weight) in &[
+        (0, 0, (1.0 - fx) * (1.0 - fy)),
+        (1, 0, fx * (1.0 - fy)),
+        (0, 1, (1.0 - fx) * fy),
+        (1, 1, fx * fy),
+    ] {
+        let sx = x0 + dx;
+        let sy = y0 + dy;
+        if weight <= 0.0 || sx < 0 || sy < 0 || sx >= size.width || sy >= size.height {
+            continue;
+        }
+        let pixel = raw_pixels[(sy * size.width + sx) as usize];
+        let a = ((pixel >> PIXEL_RGBA.transp_offset) & 0xFF) as f32 * weight;
+        channels[0] += ((pixel >> PIXEL_RGBA.red_offset) & 0xFF) as f32 * a;
+        channels[1] += ((pixel >> PIXEL_RGBA.green_offset) & 0xFF) as f32 * a;
+        channels[2] += ((pixel >> PIXEL_RGBA.blue_offset) & 0xFF) as f32 * a;
+        channels[3] += a;
+    }
+
+    if channels[3] < 1.0 / 255.0 {
+        return 0;
+    }
+    let r = (channels[0] / channels[3]).min(255.0) as u32;
+    let g = (channels[1] / channels[3]).min(255.0) as u32;
+    let b = (channels[2] / channels[3]).min(255.0) as u32;
+    let a = channels[3].round().min(255.0) as u32;
+    (r << PIXEL_RGBA.red_offset)
+        | (g << PIXEL_RGBA.green_offset)
+        | (b << PIXEL_RGBA.blue_offset)
+        | (a << PIXEL_RGBA.transp_offset)
+}
@@ -0,0 +1,372 @@
+extern crate image;
+extern crate serde_json;
+
+use std::fs;
+use std::time;
+
+use image::{imageops, RgbaImage};
+
+use dimension::*;
+use screen_writer::ScreenInfo;
+use sprite::{pixels_from_rgba, Sprite};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PlaybackMode {
+    Loop,
+    /// Plays forward and backward again, repeatedly.
+    PingPong,
+    /// Stops on the last frame.
+    Once,
+}
+
+/// Plays the frames of a sprite sheet at `fps`. Frames are laid out either on a grid or
+/// described by a JSON atlas. The sprite is driven by the scene clock and its node is only
+/// drawn again when the frame changes.
+pub struct SpriteSheetSprite {
+    pub gravity: Gravity,
+    pub filter: imageops::FilterType,
+    pub fps: f32,
+    pub mode: PlaybackMode,
+    /// Called with the new frame index every time the frame changes.
+    pub on_frame_changed: Option<Box<dyn FnMut(usize)>>,
+    sheet: Option<RgbaImage>,
+    frames: Vec<Rect>,
+    current_frame: usize,
+    playing: bool,
+    play_started: Option<time::Duration>,
+    start_frame: usize,
+    /// Frame changed by `set_frame`, to draw on the next update.
+    frame_set: bool,
+    raw_pixels: Vec<u32>,
+    frame: Rect,
+}
+
+impl SpriteSheetSprite {
+    pub fn new() -> SpriteSheetSprite {
+        SpriteSheetSprite {
+            gravity: GRAVITY_CENTER,
+            filter: imageops::Triangle,
+            fps: 12.0,
+            mode: PlaybackMode::Loop,
+            on_frame_changed: None,
+            sheet: None,
+            frames: Vec::new(),
+            current_frame: 0,
+            playing: true,
+            play_started: None,
+            start_frame: 0,
+            frame_set: false,
+            raw_pixels: Vec::new(),
+            frame: RECT_ZERO,
+        }
+    }
+
+    pub fn set_sheet_filename(&mut self, filename: &str) {
+        match image::open(filename) {
+            Ok(image) => {
+                let image = image.to_rgba();
+                if self.frames.is_empty() {
+                    self.frames = vec![Rect {
+                        pos: POS_ZERO,
+                        size: Size {
+                            width: image.width() as i32,
+                            height: image.height() as i32,
+                        },
+                    }];
+                }
+                self.sheet = Some(image);
+            }
+            Err(e) => println!("{} {}", e, filename),
+        }
+    }
+
+    /// Splits the sheet into `columns` x `rows` frames read row by row. `frame_count` limits the
+    /// number of frames when the last row is not full.
+    pub fn set_grid(&mut self, columns: u32, rows: u32, frame_count: Option<usize>) {
+        if let Some(ref sheet) = self.sheet {
+            let columns = columns.max(1);
+            let rows = rows.max(1);
+            let frame_width = (sheet.width() / columns) as i32;
+            let frame_height = (sheet.height() / rows) as i32;
+            let frame_count = frame_count.unwrap_or((columns * rows) as usize);
+            self.frames = (0..columns * rows)
+                .take(frame_count)
+                .map(|index| Rect {
+                    pos: Pos {
+                        x: (index % columns) as i32 * frame_width,
+                        y: (index / columns) as i32 * frame_height,
+                    },
+                    size: Size {
+                        width: frame_width,
+                        height: frame_height,
+                    },
+                })
+                .collect();
+            self.current_frame = 0;
+        }
+    }
+
+    /// Reads frames from a JSON atlas in the TexturePacker format, with `frames` either as an
+    /// array or as an object keyed by frame name. Named frames are played in name order. The
+    /// sheet is set first, atlases with frames outside of it are rejected.
+    pub fn set_atlas_filename(&mut self, filename: &str) -> Result<(), String> {
+        let (sheet_width, sheet_height) = match self.sheet {
+            Some(ref sheet) => (sheet.width() as i64, sheet.height() as i64),
+            None => return Err(format!("Atlas without a sheet ({}).", filename)),
+        };
+        let data = fs::read_to_string(filename).map_err(|e| format!("{} {}", e, filename))?;
+        let atlas: serde_json::Value = serde_json::from_str(&data).map_err(|e| format!("{} {}", e, filename))?;
+
+        let frame_values: Vec<&serde_json::Value> = match atlas.get("frames") {
+            Some(serde_json::Value::Array(frames)) => frames.iter().collect(),
+            Some(serde_json::Value::Object(frames)) => {
+                let mut names: Vec<&String> = frames.keys().collect();
+                names.sort();
+                names.iter().map(|name| &frames[name.as_str()]).collect()
+            }
+            _ => return Err(format!("Atlas has no frames ({}).", filename)),
+        };
+
+        let mut frames = Vec::new();
+        for frame_value in frame_values {
+            let rect = frame_value.get("frame").unwrap_or(frame_value);
+            let value = |name: &str| rect.get(name).and_then(|value| value.as_i64());
+            match (value("x"), value("y"), value("w"), value("h")) {
+                (Some(x), Some(y), Some(width), Some(height))
+                    if x >= 0 && y >= 0 && width > 0 && height > 0
+                        && x <= sheet_width - width && y <= sheet_height - height =>
+                {
+                    frames.push(Rect {
+                        pos: Pos { x: x as i32, y: y as i32 },
+                        size: Size { width: width as i32, height: height as i32 },
+                    })
+                }
+                _ => return Err(format!("Wrong atlas frame {} ({}).", frame_value, filename)),
+            }
+        }
+        self.frames = frames;
+        self.current_frame = 0;
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn current_frame(&self) -> usize {
+        self.current_frame
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Continues playing from the current frame. A finished `Once` animation starts over.
+    pub fn play(&mut self) {
+        if !self.playing {
+            self.playing = true;
+            self.play_started = None;
+            self.start_frame = if self.mode == PlaybackMode::Once && self.current_frame + 1 >= self.frames.len() {
+                0
+            } else {
+                self.current_frame
+            };
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Jumps to `frame`; takes effect on the next scene frame.
+    pub fn set_frame(&mut self, frame: usize) {
+        if frame < self.frames.len() {
+            self.start_frame = frame;
+            self.play_started = None;
+            if self.change_frame(frame) {
+                self.frame_set = true;
+            }
+        }
+    }
+
+    fn change_frame(&mut self, frame: usize) -> bool {
+        if frame == self.current_frame {
+            return false;
+        }
+        self.current_frame = frame;
+        if let Some(ref mut on_frame_changed) = self.on_frame_changed {
+            on_frame_changed(frame);
+        }
+        true
+    }
+
+    /// Frame shown after `ticks` frame durations since `start_frame`.
+    fn frame_for_ticks(&self, ticks: usize) -> usize {
+        let frame_count = self.frames.len();
+        match self.mode {
+            PlaybackMode::Loop => (self.start_frame + ticks) % frame_count,
+            PlaybackMode::Once => (self.start_frame + ticks).min(frame_count - 1),
+            PlaybackMode::PingPong => {
+                let period = 2 * (frame_count - 1);
+                let position = (self.start_frame + ticks) % period;
+                if position < frame_count {
+                    position
+                } else {
+                    period - position
+                }
+            }
+        }
+    }
+}
+
+impl Default for SpriteSheetSprite {
+    fn default() -> SpriteSheetSprite {
+        SpriteSheetSprite::new()
+    }
+}
+
+impl<'a> Sprite<'a> for SpriteSheetSprite {
+    fn draw(&mut self, outer_rect: &Rect, _screen_info: &ScreenInfo) {
+        self.raw_pixels.clear();
+        self.frame = RECT_ZERO;
+        let frame_rect = match self.frames.get(self.current_frame) {
+            Some(frame_rect) => *frame_rect,
+            None => return,
+        };
+        if let Some(ref mut sheet) = self.sheet {
+            if frame_rect.size.width <= 0 || frame_rect.size.height <= 0 {
+                return;
+            }
+            let frame_aspect = outer_rect.size.width as f32 / outer_rect.size.height as f32;
+            let image_aspect = frame_rect.size.width as f32 / frame_rect.size.height as f32;
+            let (width, height) = if frame_aspect < image_aspect {
+                (outer_rect.size.width, (outer_rect.size.width as f32 / image_aspect) as i32)
+            } else {
+                ((outer_rect.size.height as f32 * image_aspect) as i32, outer_rect.size.height)
+            };
+
+            let frame_image = imageops::crop(
+                sheet,
+                frame_rect.pos.x as u32,
+                frame_rect.pos.y as u32,
+                frame_rect.size.width as u32,
+                frame_rect.size.height as u32,
+            ).to_image();
+            let frame_image = imageops::resize(&frame_image, width.max(1) as u32, height.max(1) as u32, self.filter);
+
+            let mut frame = Rect {
+                pos: POS_ZERO,
+                size: Size {
+                    width: frame_image.width() as i32,
+                    height: frame_image.height() as i32,
+                },
+            };
+            frame.pos.x = ((outer_rect.size.width as f32 * self.gravity.x) - (frame.size.width as f32 * self.gravity.x)) as i32;
+            frame.pos.y = ((outer_rect.size.height as f32 * self.gravity.y) - (frame.size.height as f32 * self.gravity.y)) as i32;
+            self.raw_pixels = pixels_from_rgba(&frame_image.into_raw());
+            self.frame = frame;
+        }
+    }
+
+    fn pixels(&self) -> (&[u32], &Rect) {
+        (&self.raw_pixels, &self.frame)
+    }
+
+    fn update(&mut self, clock: time::Duration) -> bool {
+        let frame_set = self.frame_set;
+        self.frame_set = false;
+        if !self.playing || self.frames.len() < 2 || self.fps <= 0.0 {
+            return frame_set;
+        }
+        let play_started = *self.play_started.get_or_insert(clock);
+        let elapsed = clock.checked_sub(play_started).unwrap_or_default();
+        let ticks = (elapsed.as_secs_f64() * self.fps as f64) as usize;
+
+        let frame = self.frame_for_ticks(ticks);
+        if self.mode == PlaybackMode::Once && frame == self.frames.len() - 1 {
+            self.playing = false;
+        }
+        self.change_frame(frame) || frame_set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate tempdir;
+
+    fn sprite_sheet(mode: PlaybackMode, frame_count: usize) -> SpriteSheetSprite {
+        let mut sprite_sheet = SpriteSheetSprite::new();
+        sprite_sheet.mode = mode;
+        sprite_sheet.fps = 10.0;
+        sprite_sheet.frames = vec![RECT_ZERO; frame_count];
+        sprite_sheet
+    }
+
+    fn frames_for_ticks(sprite_sheet: &SpriteSheetSprite, ticks: usize) -> Vec<usize> {
+        (0..ticks).map(|tick| sprite_sheet.frame_for_ticks(tick)).collect()
+    }
+
+    #[test]
+    fn modes_step_through_frames() {
+        assert_eq!(frames_for_ticks(&sprite_sheet(PlaybackMode::Loop, 3), 7), vec![0, 1, 2, 0, 1, 2, 0]);
+        assert_eq!(frames_for_ticks(&sprite_sheet(PlaybackMode::Once, 3), 5), vec![0, 1, 2, 2, 2]);
+        assert_eq!(
+            frames_for_ticks(&sprite_sheet(PlaybackMode::PingPong, 4), 10),
+            vec![0, 1, 2, 3, 2, 1, 0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn ping_pong_starts_from_the_start_frame() {
+        let mut sprite_sheet = sprite_sheet(PlaybackMode::PingPong, 3);
+        sprite_sheet.start_frame = 2;
+        assert_eq!(frames_for_ticks(&sprite_sheet, 5), vec![2, 1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut sprite_sheet = sprite_sheet(PlaybackMode::Once, 3);
+        let at = |millis: u64| time::Duration::from_millis(millis);
+        assert!(!sprite_sheet.update(at(0)));
+        assert!(sprite_sheet.update(at(100)));
+        assert!(sprite_sheet.update(at(250)));
+        assert_eq!(sprite_sheet.current_frame(), 2);
+        assert!(!sprite_sheet.is_playing());
+        assert!(!sprite_sheet.update(at(400)));
+        sprite_sheet.play();
+        assert!(sprite_sheet.update(at(500)));
+        assert_eq!(sprite_sheet.current_frame(), 0);
+    }
+
+    #[test]
+    fn set_frame_is_drawn_on_the_next_update() {
+        let mut sprite_sheet = sprite_sheet(PlaybackMode::Loop, 3);
+        sprite_sheet.pause();
+        sprite_sheet.set_frame(2);
+        assert!(sprite_sheet.update(time::Duration::from_millis(0)));
+        assert!(!sprite_sheet.update(time::Duration::from_millis(100)));
+        assert_eq!(sprite_sheet.current_frame(), 2);
+    }
+
+    #[test]
+    fn atlas_frames_are_checked_against_the_sheet() {
+        let dir = tempdir::TempDir::new("sprite_sheet").unwrap();
+        let sheet_path = dir.path().join("sheet.png");
+        RgbaImage::new(8, 8).save(&sheet_path).unwrap();
+        let atlas = |name: &str, frame: &str| {
+            let atlas_path = dir.path().join(name);
+            fs::write(&atlas_path, format!(r#"{{"frames": [{{"frame": {}}}]}}"#, frame)).unwrap();
+            atlas_path.to_str().unwrap().to_string()
+        };
+        let mut sprite_sheet = SpriteSheetSprite::new();
+        assert!(sprite_sheet.set_atlas_filename(&atlas("early.json", r#"{"x": 0, "y": 0, "w": 4, "h": 4}"#)).is_err());
+        sprite_sheet.set_sheet_filename(sheet_path.to_str().unwrap());
+        assert!(sprite_sheet.set_atlas_filename(&atlas("inside.json", r#"{"x": 4, "y": 4, "w": 4, "h": 4}"#)).is_ok());
+        assert!(sprite_sheet.set_atlas_filename(&atlas("outside.json", r#"{"x": 6, "y": 0, "w": 4, "h": 4}"#)).is_err());
+        assert!(sprite_sheet.set_atlas_filename(&atlas("negative.json", r#"{"x": -1, "y": 0, "w": 4, "h": 4}"#)).is_err());
+        assert!(sprite_sheet.set_atlas_filename(&atlas("empty.json", r#"{"x": 0, "y": 0, "w": 0, "h": 4}"#)).is_err());
+        assert_eq!(sprite_sheet.frame_count(), 1);
+    }
+}
@@ -1,25 +1,102 @@
 extern crate rusttype;
 extern crate unicode_bidi;
 
-use std::cmp;
 use std::fs;
-use std::i32;
-use rusttype::{Font, FontCollection, point, PositionedGlyph};
+use std::path::Path;
+use std::rc::Rc;
+use std::time;
+use rusttype::point;
+use unicode_bidi::BidiClass;
 
+use canvas::{blend_over, Canvas};
+use clock::Clock;
 use color::*;
 use color;
 use dimension::*;
+use effect::Shadow;
+use font::{SharedFontList, TextFont};
+use glyph_cache::{GlyphBitmap, GlyphCache, SharedGlyphCache};
 use screen_writer::*;
-use sprite::{Sprite, render_to_canvas};
+use sprite::Sprite;
+use rich_text::{parse_markup, TextSpan};
+use text_layout::{layout_text, LayoutLine, StyleRun};
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+    /// Stretches the spaces of wrapped lines to the node width, last lines of paragraphs are
+    /// aligned left.
+    Justify,
+}
+
+/// Where text that overflows the node is cut and replaced by an ellipsis, in logical order,
+/// so the end of right to left text is on its left.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextEllipsis {
+    Start,
+    Middle,
+    End,
+}
+
+const ELLIPSIS: &str = "\u{2026}";
+
+/// Steps of the search for the font size that fits the node.
+const AUTO_FIT_STEPS: usize = 8;
+
+/// Stroke around the glyphs, drawn under the text.
+#[derive(Debug, Copy, Clone)]
+pub struct TextOutline {
+    pub width: f32,
+    pub color: Color,
+}
+
+impl TextOutline {
+    pub fn new(width: f32, color: Color) -> TextOutline {
+        TextOutline { width, color }
+    }
+}
 
 pub struct TextSprite {
     pub color: Color,
     pub gravity : Gravity,
     pub scale : Scale,
+    /// Font size as a fraction of the node height, used when `font_size` is None.
     pub height : f32,
+    /// Font size in pixels.
+    pub font_size : Option<f32>,
+    /// Breaks lines at the node width, in addition to the newlines of the text.
+    pub wrap : bool,
+    pub align : TextAlign,
+    /// Multiplies the line height of the font.
+    pub line_spacing : f32,
+    pub max_lines : Option<usize>,
+    /// Smallest font size in pixels the text shrinks to until it fits the node, None keeps the
+    /// size.
+    pub auto_fit : Option<f32>,
+    /// Cuts text that does not fit the node, after shrinking it.
+    pub ellipsis : Option<TextEllipsis>,
     pub text : String,
-    pub font : Font<'static>,
+    /// Styled spans drawn instead of `text` when not empty.
+    pub spans : Vec<TextSpan>,
+    pub font : TextFont,
+    /// Fonts to draw the chars `font` lacks from, tried in order before the fallback fonts of
+    /// the scene and the embedded font.
+    pub fallback_fonts : Vec<TextFont>,
+    /// Replaces `text` with the formatted time whenever it changes.
+    pub clock : Option<Clock>,
+    pub outline : Option<TextOutline>,
+    /// Shadow of the text with its outline and decoration lines.
+    pub shadow : Option<Shadow>,
+    pub underline : bool,
+    pub strikethrough : bool,
+    /// Fills the box of the laid out lines behind the text.
+    pub background : Option<Color>,
+    /// Space around the lines covered by the background, in pixels.
+    pub background_padding : i32,
+    scene_fallback_fonts : Option<SharedFontList>,
+    glyph_cache : Option<SharedGlyphCache>,
     frame: Rect,
     raw_pixels : Vec<u32>,
 }
@@ -27,101 +104,564 @@ pub struct TextSprite {
 impl TextSprite {
 
     pub fn new() -> TextSprite {
-
-        let font = FontCollection::from_bytes(get_default_font_data()).unwrap().into_font().unwrap();
-
         TextSprite {
             color : color::WHITE,
             gravity : GRAVITY_CENTER,
-            font : font,
+            font : TextFont::default(),
+            fallback_fonts : Vec::new(),
+            scene_fallback_fonts : None,
+            glyph_cache : None,
             height : 1.0,
+            font_size : None,
+            wrap : false,
+            align : TextAlign::Left,
+            line_spacing : 1.0,
+            max_lines : None,
+            auto_fit : None,
+            ellipsis : None,
             scale : SCALE_SINGLE,
             text : String::new(),
+            spans : Vec::new(),
+            clock : None,
+            outline : None,
+            shadow : None,
+            underline : false,
+            strikethrough : false,
+            background : None,
+            background_padding : 0,
             frame: RECT_ZERO,
             raw_pixels : Vec::new(),
         }
     }
 
     pub fn set_font_file(&mut self, file: &mut fs::File) {
-        use std::io::Read;
-        let mut font_data : Vec<u8> = Vec::new();
-        if let Ok(_) = file.read_to_end(&mut font_data) {
-            self.font = FontCollection::from_bytes(font_data).unwrap().into_font().unwrap();
+        match TextFont::from_file(file) {
+            Ok(font) => self.font = font,
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    pub fn set_scene_fallback_fonts(&mut self, fonts: &SharedFontList) {
+        self.scene_fallback_fonts = Some(fonts.clone());
+    }
+
+    pub fn set_glyph_cache(&mut self, glyph_cache: &SharedGlyphCache) {
+        self.glyph_cache = Some(glyph_cache.clone());
+    }
+
+    /// Replaces the text with spans parsed from inline markup, see `parse_markup`. Font files
+    /// are relative to the working directory.
+    pub fn set_markup(&mut self, markup: &str) -> Result<(), String> {
+        self.spans = parse_markup(markup, Path::new(""))?;
+        self.text = self.spans.iter().map(|span| span.text.as_str()).collect();
+        Ok(())
+    }
+
+    /// Text to draw with the style runs to lay it out with, and the color and slant of each run.
+    fn style_runs(&self, scale: rusttype::Scale, factor: f32) -> (String, Vec<StyleRun>, Vec<(Color, bool)>) {
+        let fonts = self.font_chain();
+        if self.spans.is_empty() {
+            let style = StyleRun { range: 0..self.text.len(), fonts, scale, bold: false };
+            return (self.text.clone(), vec![style], vec![(self.color, false)]);
+        }
+        let mut text = String::new();
+        let mut styles = Vec::new();
+        let mut paints = Vec::new();
+        for span in &self.spans {
+            let start = text.len();
+            text.push_str(&span.text);
+            let mut span_fonts = fonts.clone();
+            if let Some(ref font) = span.style.font {
+                span_fonts.retain(|chain_font| chain_font != font);
+                span_fonts.insert(0, font.clone());
+            }
+            let scale = match span.style.font_size {
+                Some(size) => rusttype::Scale { x: size * factor * self.scale.x, y: size * factor * self.scale.y },
+                None => scale,
+            };
+            styles.push(StyleRun { range: start..text.len(), fonts: span_fonts, scale, bold: span.style.bold });
+            paints.push((span.style.color.unwrap_or(self.color), span.style.italic));
         }
+        (text, styles, paints)
+    }
+
+    /// Lines fit the node, unwrapped lines its width and, if `check_height` is set, all lines
+    /// its height.
+    fn fits(&self, lines: &[LayoutLine], size: &Size, check_height: bool) -> bool {
+        if self.max_lines.is_some_and(|max_lines| lines.len() > max_lines) {
+            return false;
+        }
+        if !self.wrap && lines.iter().any(|line| line.width > size.width as f32) {
+            return false;
+        }
+        !check_height || text_height(lines, self.line_spacing) <= size.height as f32
+    }
+
+    /// Factor of the font size which fits the text in the node, no smaller than the minimum
+    /// size.
+    fn auto_fit_factor(&self, height: f32, min_size: f32, size: &Size, max_width: Option<f32>) -> f32 {
+        let fits = |factor: f32| {
+            let scale = rusttype::Scale { x: height * factor * self.scale.x, y: height * factor * self.scale.y };
+            let (text, styles, _) = self.style_runs(scale, factor);
+            self.fits(&layout_text(&text, &styles, max_width, None), size, true)
+        };
+        let min_factor = (min_size / height).min(1.0);
+        if fits(1.0) {
+            return 1.0;
+        }
+        let (mut low, mut high) = (min_factor, 1.0);
+        for _ in 0..AUTO_FIT_STEPS {
+            let factor = (low + high) / 2.0;
+            if fits(factor) {
+                low = factor;
+            } else {
+                high = factor;
+            }
+        }
+        low
+    }
+
+    /// Text with the most chars kept around the ellipsis that fits the node.
+    fn elide_to_fit(
+        &self,
+        ellipsis: TextEllipsis,
+        (text, styles, paints): (String, Vec<StyleRun>, Vec<(Color, bool)>),
+        size: &Size,
+        max_width: Option<f32>,
+    ) -> (String, Vec<StyleRun>, Vec<(Color, bool)>) {
+        let fits = |text: &str, styles: &[StyleRun]| self.fits(&layout_text(text, styles, max_width, None), size, self.wrap);
+        if text.is_empty() || fits(&text, &styles) {
+            return (text, styles, paints);
+        }
+        // Cuts between chars, keeping marks and joiners with the char before them.
+        let boundaries: Vec<usize> = text
+            .char_indices()
+            .filter(|&(_, c)| !matches!(unicode_bidi::bidi_class(c), BidiClass::NSM | BidiClass::BN))
+            .map(|(index, _)| index)
+            .chain(Some(text.len()))
+            .collect();
+        let cut = boundaries.len() - 1;
+        let kept = |keep: usize| {
+            let (head, tail) = match ellipsis {
+                TextEllipsis::Start => (0, boundaries[cut - keep]),
+                TextEllipsis::Middle => (boundaries[keep.div_ceil(2)], boundaries[cut - keep / 2]),
+                TextEllipsis::End => (boundaries[keep], text.len()),
+            };
+            // No whitespace next to the ellipsis.
+            let head = text[..head].trim_end().len();
+            let tail = text.len() - text[tail..].trim_start().len();
+            elide(&text, &styles, &paints, head, tail)
+        };
+        let (mut low, mut high) = (0, cut - 1);
+        while low < high {
+            let keep = (low + high).div_ceil(2);
+            let (elided_text, elided_styles, _) = kept(keep);
+            if fits(&elided_text, &elided_styles) {
+                low = keep;
+            } else {
+                high = keep - 1;
+            }
+        }
+        kept(low)
+    }
+
+    /// Font followed by the fallback fonts, without duplicates.
+    fn font_chain(&self) -> Vec<TextFont> {
+        let mut fonts = vec![self.font.clone()];
+        let scene_fonts = self.scene_fallback_fonts.as_ref().map(|fonts| fonts.borrow().clone()).unwrap_or_default();
+        for font in self.fallback_fonts.iter().chain(&scene_fonts).cloned().chain(Some(TextFont::default())) {
+            if !fonts.contains(&font) {
+                fonts.push(font);
+            }
+        }
+        fonts
     }
 }
 
 impl<'a> Sprite<'a> for TextSprite {
     fn draw(&mut self, outer_rect:&Rect, _screen_info:&ScreenInfo) {
-        let height = outer_rect.size.height as f32 * self.height;
-        let scale = rusttype::Scale { x: height * self.scale.x, y: height * self.scale.y};
-        let v_metrics = self.font.v_metrics(scale);
-        let offset = point(0.0, v_metrics.ascent);
-
-        let normalized_text = {
-            let bidi_info = unicode_bidi::BidiInfo::new(self.text.as_ref(), None);
-            if bidi_info.paragraphs.len() > 0 {
-                let para = &bidi_info.paragraphs[0];
-                let line = para.range.clone();
-                String::from(bidi_info.reorder_line(para, line))
-            } else {
-                self.text.clone()
-            }
+        let height = self.font_size.unwrap_or(outer_rect.size.height as f32 * self.height);
+        let max_width = if self.wrap { Some(outer_rect.size.width as f32) } else { None };
+        let factor = match self.auto_fit {
+            Some(min_size) if height > 0.0 => self.auto_fit_factor(height, min_size, &outer_rect.size, max_width),
+            _ => 1.0,
         };
+        let scale = rusttype::Scale { x: height * factor * self.scale.x, y: height * factor * self.scale.y};
 
+        let mut style_runs = self.style_runs(scale, factor);
+        if let Some(ellipsis) = self.ellipsis {
+            style_runs = self.elide_to_fit(ellipsis, style_runs, &outer_rect.size, max_width);
+        }
+        let (text, styles, paints) = style_runs;
+        // Without a scene cache glyphs are only shared within this draw.
+        let glyph_cache = self.glyph_cache.clone().unwrap_or_else(|| GlyphCache::new_shared(usize::MAX));
+        let mut glyph_cache = glyph_cache.borrow_mut();
+        let lines = layout_text(&text, &styles, max_width, self.max_lines);
+        let mut glyphs: Vec<(Rc<GlyphBitmap>, Pos, Color)> = Vec::new();
+        // Left, right and baseline of the laid out lines.
+        let mut spans: Vec<(f32, f32, f32)> = Vec::new();
+        let mut baseline = 0.0;
+        // Unwrapped lines are aligned against the widest of them.
+        let layout_width = max_width.unwrap_or_else(|| lines.iter().map(|line| line.width).fold(0.0, f32::max));
+        for (line_index, line) in lines.iter().enumerate() {
+            let is_space = |cluster: usize| text[cluster..].starts_with(' ');
+            let (mut x, space_extra) = match self.align {
+                TextAlign::Left => (0.0, 0.0),
+                TextAlign::Center => ((layout_width - line.width) / 2.0, 0.0),
+                TextAlign::Right => (layout_width - line.width, 0.0),
+                TextAlign::Justify => {
+                    // Trailing whitespace is not part of the line.
+                    let spaces = line.glyphs.iter().filter(|glyph| is_space(glyph.cluster)).count();
+                    if line.last || spaces == 0 || max_width.is_none() {
+                        (0.0, 0.0)
+                    } else {
+                        (0.0, (layout_width - line.width) / spaces as f32)
+                    }
+                }
+            };
+            baseline += match line_index {
+                0 => line.ascent,
+                _ => {
+                    let previous = &lines[line_index - 1];
+                    (line.ascent - previous.descent + previous.line_gap) * self.line_spacing
+                }
+            };
+            let line_start = x;
+            for glyph in &line.glyphs {
+                let style = &styles[glyph.style];
+                let (color, italic) = paints[glyph.style];
+                let position = point(x + glyph.x_offset, baseline - glyph.y_offset);
+                let (bitmap, origin) = glyph_cache.glyph(&style.fonts[glyph.font], glyph.id, style.scale, position, style.bold, italic);
+                if !bitmap.coverage.is_empty() {
+                    let pos = Pos { x: origin.x + bitmap.offset.x, y: origin.y + bitmap.offset.y };
+                    glyphs.push((bitmap, pos, color));
+                }
+                x += glyph.advance;
+                if is_space(glyph.cluster) {
+                    x += space_extra;
+                }
+            }
+            spans.push((line_start, x, baseline));
+        }
 
-        let glyphs: Vec<PositionedGlyph> = self.font.layout(normalized_text.as_ref(), scale, offset).collect();
+        if glyphs.is_empty() {
+            self.raw_pixels.clear();
+            self.frame = RECT_ZERO;
+            return;
+        }
 
-        let text_frame = {
-            let mut min_x = i32::MAX;
-            let mut max_x = 0_i32;
-            let mut min_y = i32::MAX;
-            let mut max_y = 0_i32;
-            for g in glyphs.clone() {
-                if let Some(bb) = g.pixel_bounding_box() {
-                    min_x = cmp::min(min_x, bb.min.x);
-                    max_x = cmp::max(max_x, bb.max.x);
-                    min_y = cmp::min(min_y, bb.min.y);
-                    max_y = cmp::max(max_y, bb.max.y);
+        let mut decoration_lines = Vec::new();
+        for &(strikethrough, enabled) in &[(false, self.underline), (true, self.strikethrough)] {
+            if enabled {
+                let (position, thickness) = self.font.decoration_line(strikethrough, scale);
+                for &(left, right, baseline) in spans.iter().filter(|&&(left, right, _)| right > left) {
+                    decoration_lines.push(Rect {
+                        pos: Pos { x: left.round() as i32, y: (baseline - position).round() as i32 },
+                        size: Size { width: (right - left).round() as i32, height: thickness.round().max(1.0) as i32 },
+                    });
                 }
             }
-            Rect {pos : Pos {x : min_x, y : min_y} , size : Size {width : max_x - min_x, height : max_y - min_y}}
+        }
+
+        // Coverage of the glyphs and decoration lines, with room for the outline.
+        let outline_width = self.outline.map_or(0, |outline| outline.width.max(0.0).ceil() as i32);
+        let ink_frame = glyphs
+            .iter()
+            .map(|(bitmap, pos, _)| Rect { pos: *pos, size: bitmap.size })
+            .chain(decoration_lines.iter().cloned())
+            .fold(None, |frame: Option<Rect>, rect| Some(frame.map_or(rect, |frame| union_rect(&frame, &rect))))
+            .unwrap_or(RECT_ZERO);
+        let text_frame = Rect {
+            pos: Pos { x: ink_frame.pos.x - outline_width, y: ink_frame.pos.y - outline_width },
+            size: Size { width: ink_frame.size.width + outline_width * 2, height: ink_frame.size.height + outline_width * 2 },
         };
+        let buffer_size = (text_frame.size.width * text_frame.size.height) as usize;
+        let mut coverage = vec![0u8; buffer_size];
+        let mut text_pixels = vec![0u32; buffer_size];
+        for (bitmap, pos, color) in &glyphs {
+            let left = pos.x - text_frame.pos.x;
+            let top = pos.y - text_frame.pos.y;
+            for (row, src_row) in bitmap.coverage.chunks(bitmap.size.width as usize).enumerate() {
+                let dst_offset = ((top + row as i32) * text_frame.size.width + left) as usize;
+                let dst_range = dst_offset..dst_offset + src_row.len();
+                for ((dst, pixel), &a) in coverage[dst_range.clone()].iter_mut().zip(&mut text_pixels[dst_range]).zip(src_row) {
+                    // Glyphs of neighboring lines may overlap, keep the higher coverage.
+                    if *dst <= a {
+                        *dst = a;
+                        let color::Color(c) = color.color_with_alpha(a);
+                        *pixel = c;
+                    }
+                }
+            }
+        }
+        let color::Color(line_pixel) = self.color.color_with_alpha(0xFF);
+        for rect in &decoration_lines {
+            for y in rect.pos.y..rect.pos.y + rect.size.height {
+                let dst_offset = ((y - text_frame.pos.y) * text_frame.size.width + rect.pos.x - text_frame.pos.x) as usize;
+                let dst_range = dst_offset..dst_offset + rect.size.width as usize;
+                for (dst, pixel) in coverage[dst_range.clone()].iter_mut().zip(&mut text_pixels[dst_range]) {
+                    *dst = 0xFF;
+                    *pixel = line_pixel;
+                }
+            }
+        }
+
+        if let Some(outline) = self.outline {
+            let stroke = dilate(&coverage, &text_frame.size, outline.width);
+            let (_, _, _, outline_alpha) = outline.color.rgba();
+            for (dst, &a) in text_pixels.iter_mut().zip(&stroke) {
+                let color::Color(c) = outline.color.color_with_alpha((a as u32 * outline_alpha as u32 / 0xFF) as u8);
+                *dst = blend_over(c, *dst);
+            }
+        }
 
-        let mut frame = Rect {pos : POS_ZERO, size : text_frame.size};
+        let shadow = self.shadow.map(|shadow| shadow.render(&text_pixels, &text_frame));
+        let background = self.background.map(|color| {
+            let left = spans.iter().map(|&(left, _, _)| left).fold(f32::MAX, f32::min);
+            let right = spans.iter().map(|&(_, right, _)| right).fold(f32::MIN, f32::max);
+            let top = spans[0].2 - lines[0].ascent;
+            let bottom = spans[spans.len() - 1].2 - lines[lines.len() - 1].descent;
+            let padding = self.background_padding;
+            let rect = Rect {
+                pos: Pos { x: left.floor() as i32 - padding, y: top.floor() as i32 - padding },
+                size: Size {
+                    width: (right.ceil() - left.floor()) as i32 + padding * 2,
+                    height: (bottom.ceil() - top.floor()) as i32 + padding * 2,
+                },
+            };
+            (rect, color)
+        });
 
-        let buffer_size = (frame.size.width * frame.size.height) as usize;
+        let mut bounds = text_frame;
+        if let Some(ref shadow) = shadow {
+            bounds = union_rect(&bounds, &shadow.frame);
+        }
+        if let Some((ref rect, _)) = background {
+            bounds = union_rect(&bounds, rect);
+        }
+        self.raw_pixels = if shadow.is_none() && background.is_none() {
+            text_pixels
+        } else {
+            let relative = |pos: &Pos| Pos { x: pos.x - bounds.pos.x, y: pos.y - bounds.pos.y };
+            let mut canvas = Canvas::new(bounds.size.width, bounds.size.height);
+            if let Some((rect, color)) = background {
+                canvas.fill_rect(&Rect { pos: relative(&rect.pos), size: rect.size }, color);
+            }
+            if let Some(shadow) = shadow {
+                canvas.draw_pixels(&relative(&shadow.frame.pos), &shadow.raw_pixels, &shadow.frame.size);
+            }
+            canvas.draw_pixels(&relative(&text_frame.pos), &text_pixels, &text_frame.size);
+            canvas.pixels().to_vec()
+        };
 
-        self.raw_pixels = vec![0; buffer_size];
-        let raw_pixels_ptr = self.raw_pixels.as_mut_slice().as_mut_ptr();
+        let mut frame = Rect {pos : POS_ZERO, size : bounds.size};
 
-        frame.pos.x = ((outer_rect.size.width as f32 * self.gravity.x) - (frame.size.width as f32 * self.gravity.x)) as i32;
+        // Wrapped lines keep their alignment within the node width, otherwise the drawn text
+        // is placed by gravity.
+        frame.pos.x = match max_width {
+            Some(max_width) => ((outer_rect.size.width as f32 - max_width) * self.gravity.x) as i32 + bounds.pos.x,
+            None => ((outer_rect.size.width as f32 * self.gravity.x) - (frame.size.width as f32 * self.gravity.x)) as i32,
+        };
         frame.pos.y = ((outer_rect.size.height as f32 * self.gravity.y) - (frame.size.height as f32 * self.gravity.y)) as i32;
+        self.frame = frame;
+    }
 
+    fn pixels(&self) -> (&[u32], &Rect) {
+        (&self.raw_pixels, &self.frame)
+    }
 
-        for g in glyphs.clone() {
-            if let Some(bb) = g.pixel_bounding_box() {
-                g.draw(|gx, gy, gv| {
-                    let gx = gx as i32 + bb.min.x - text_frame.pos.x as i32;
-                    let gy = gy as i32 + bb.min.y - text_frame.pos.y as i32;
-                    let a = (gv * 255.0) as u8;
-                    let dst_offset = (gy * frame.size.width as i32 + gx) as isize;
-                    unsafe {
-                        let color::Color(c) = self.color.color_with_alpha(a);
-                        *raw_pixels_ptr.offset(dst_offset) = c;
-                    }
-                })
+    fn update(&mut self, _clock: time::Duration) -> bool {
+        if let Some(ref clock) = self.clock {
+            let text = clock.text();
+            if text != self.text {
+                self.text = text;
+                return true;
             }
         }
-        self.frame = frame;
+        false
+    }
+}
+
+/// Height of the lines from the top of the first to the bottom of the last.
+fn text_height(lines: &[LayoutLine], line_spacing: f32) -> f32 {
+    let mut height = 0.0;
+    for (index, line) in lines.iter().enumerate() {
+        height += match index {
+            0 => line.ascent,
+            _ => (line.ascent - lines[index - 1].descent + lines[index - 1].line_gap) * line_spacing,
+        };
+    }
+    height - lines.last().map_or(0.0, |line| line.descent)
+}
+
+/// Text with the chars between `head` and `tail` replaced by an ellipsis, in the style of
+/// the char before it.
+fn elide(
+    text: &str,
+    styles: &[StyleRun],
+    paints: &[(Color, bool)],
+    head: usize,
+    tail: usize,
+) -> (String, Vec<StyleRun>, Vec<(Color, bool)>) {
+    let ellipsis_index = if head > 0 { head - 1 } else { tail.min(text.len().saturating_sub(1)) };
+    let ellipsis_style = styles.iter().rposition(|style| style.range.start <= ellipsis_index).unwrap_or(0);
+    let mut pieces: Vec<(&str, usize)> = Vec::new();
+    for (index, style) in styles.iter().enumerate() {
+        if style.range.start < head {
+            pieces.push((&text[style.range.start..style.range.end.min(head)], index));
+        }
+    }
+    pieces.push((ELLIPSIS, ellipsis_style));
+    for (index, style) in styles.iter().enumerate() {
+        if style.range.end > tail {
+            pieces.push((&text[style.range.start.max(tail)..style.range.end], index));
+        }
     }
 
-    fn render(&mut self, parent_node_frame:&Rect, node_frame:&Rect, clip_to_bounds:bool, screen_info:&ScreenInfo, canvas_ptr:*mut u32) {
-        render_to_canvas(self.raw_pixels.as_ptr(), parent_node_frame,node_frame, &self.frame, clip_to_bounds, screen_info, canvas_ptr);
+    let mut elided_text = String::new();
+    let mut elided_styles: Vec<StyleRun> = Vec::new();
+    let mut elided_paints = Vec::new();
+    let mut last_style = None;
+    for (piece, style) in pieces {
+        elided_text.push_str(piece);
+        if last_style == Some(style) {
+            if let Some(run) = elided_styles.last_mut() {
+                run.range.end = elided_text.len();
+            }
+        } else {
+            let mut run = styles[style].clone();
+            run.range = elided_text.len() - piece.len()..elided_text.len();
+            elided_styles.push(run);
+            elided_paints.push(paints[style]);
+            last_style = Some(style);
+        }
     }
+    (elided_text, elided_styles, elided_paints)
+}
+
+fn union_rect(rect: &Rect, other: &Rect) -> Rect {
+    let left = rect.pos.x.min(other.pos.x);
+    let top = rect.pos.y.min(other.pos.y);
+    let right = (rect.pos.x + rect.size.width).max(other.pos.x + other.size.width);
+    let bottom = (rect.pos.y + rect.size.height).max(other.pos.y + other.size.height);
+    Rect { pos: Pos { x: left, y: top }, size: Size { width: right - left, height: bottom - top } }
 }
 
-fn get_default_font_data() -> Vec<u8> {
+/// Coverage grown by `width` pixels in every direction, antialiased at the edge.
+fn dilate(coverage: &[u8], size: &Size, width: f32) -> Vec<u8> {
+    let radius = width.max(0.0).ceil() as i32;
+    let mut kernel = Vec::new();
+    for dy in -radius..radius + 1 {
+        for dx in -radius..radius + 1 {
+            let weight = (width + 0.5 - ((dx * dx + dy * dy) as f32).sqrt()).min(1.0);
+            if weight > 0.0 {
+                kernel.push((dx, dy, weight));
+            }
+        }
+    }
+    let mut stroke = vec![0u8; coverage.len()];
+    for y in 0..size.height {
+        for x in 0..size.width {
+            let mut value = 0.0_f32;
+            for &(dx, dy, weight) in &kernel {
+                let (src_x, src_y) = (x + dx, y + dy);
+                if src_x >= 0 && src_y >= 0 && src_x < size.width && src_y < size.height {
+                    value = value.max(coverage[(src_y * size.width + src_x) as usize] as f32 * weight);
+                }
+            }
+            stroke[(y * size.width + x) as usize] = value as u8;
+        }
+    }
+    stroke
+}
+
+pub fn get_default_font_data() -> Vec<u8> {
     Vec::from(include_bytes!("default.ttf") as &[u8])
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Hello wonderful world of text";
+    const FONT_SIZE: f32 = 20.0;
+
+    fn text_sprite(text: &str) -> TextSprite {
+        let mut text_sprite = TextSprite::new();
+        text_sprite.text = text.to_string();
+        text_sprite
+    }
+
+    fn width(text_sprite: &TextSprite, factor: f32) -> f32 {
+        let scale = rusttype::Scale::uniform(FONT_SIZE * factor);
+        let (text, styles, _) = text_sprite.style_runs(scale, factor);
+        layout_text(&text, &styles, None, None).iter().map(|line| line.width).fold(0.0, f32::max)
+    }
+
+    fn elided(ellipsis: TextEllipsis, width: i32) -> String {
+        let text_sprite = text_sprite(TEXT);
+        let style_runs = text_sprite.style_runs(rusttype::Scale::uniform(FONT_SIZE), 1.0);
+        let size = Size { width, height: 100 };
+        let (text, styles, _) = text_sprite.elide_to_fit(ellipsis, style_runs, &size, None);
+        let lines = layout_text(&text, &styles, None, None);
+        assert!(lines[0].width <= width as f32, "{} is wider than {}", text, width);
+        text
+    }
+
+    #[test]
+    fn text_that_fits_is_not_elided() {
+        let full_width = width(&text_sprite(TEXT), 1.0).ceil() as i32;
+        assert_eq!(elided(TextEllipsis::End, full_width), TEXT);
+    }
+
+    #[test]
+    fn elided_text_keeps_the_start_or_end() {
+        let full_width = width(&text_sprite(TEXT), 1.0);
+        let half_width = (full_width / 2.0) as i32;
+
+        let end = elided(TextEllipsis::End, half_width);
+        let head = end.strip_suffix(ELLIPSIS).unwrap();
+        assert!(TEXT.starts_with(head) && !head.is_empty() && !head.ends_with(' '));
+
+        let start = elided(TextEllipsis::Start, half_width);
+        let tail = start.strip_prefix(ELLIPSIS).unwrap();
+        assert!(TEXT.ends_with(tail) && !tail.is_empty() && !tail.starts_with(' '));
+
+        let middle = elided(TextEllipsis::Middle, half_width);
+        let (head, tail) = middle.split_at(middle.find(ELLIPSIS).unwrap());
+        assert!(TEXT.starts_with(head) && TEXT.ends_with(&tail[ELLIPSIS.len()..]));
+    }
+
+    #[test]
+    fn elided_text_keeps_more_in_wider_nodes() {
+        let full_width = width(&text_sprite(TEXT), 1.0);
+        let narrow = elided(TextEllipsis::End, (full_width / 3.0) as i32);
+        let wide = elided(TextEllipsis::End, (full_width * 2.0 / 3.0) as i32);
+        assert!(wide.len() > narrow.len());
+    }
+
+    #[test]
+    fn auto_fit_keeps_the_size_of_text_that_fits() {
+        let text_sprite = text_sprite(TEXT);
+        let size = Size { width: width(&text_sprite, 1.0).ceil() as i32 + 1, height: 100 };
+        assert_eq!(text_sprite.auto_fit_factor(FONT_SIZE, 8.0, &size, None), 1.0);
+    }
+
+    #[test]
+    fn auto_fit_shrinks_text_to_the_node_width() {
+        let text_sprite = text_sprite(TEXT);
+        let size = Size { width: (width(&text_sprite, 1.0) * 0.6) as i32, height: 100 };
+        let factor = text_sprite.auto_fit_factor(FONT_SIZE, 4.0, &size, None);
+        assert!(factor < 1.0);
+        assert!(width(&text_sprite, factor) <= size.width as f32);
+        // Within the precision of the search of the largest size that fits.
+        assert!(width(&text_sprite, factor + 2.0 / (1 << AUTO_FIT_STEPS) as f32) > size.width as f32);
+    }
+
+    #[test]
+    fn auto_fit_stops_at_the_min_size() {
+        let text_sprite = text_sprite(TEXT);
+        let size = Size { width: 10, height: 100 };
+        assert_eq!(text_sprite.auto_fit_factor(FONT_SIZE, 10.0, &size, None), 0.5);
+    }
+}
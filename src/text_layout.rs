@@ -0,0 +1,330 @@
+extern crate rusttype;
+extern crate rustybuzz;
+extern crate unicode_bidi;
+extern crate unicode_linebreak;
+
+use std::ops::Range;
+
+use unicode_bidi::{BidiClass, BidiInfo, ParagraphInfo};
+use unicode_linebreak::linebreaks;
+
+use font::TextFont;
+use glyph_cache::synthetic_bold_width;
+
+/// Fonts and size a range of the text is laid out with.
+#[derive(Clone)]
+pub struct StyleRun {
+    pub range: Range<usize>,
+    /// Fallback chain, chars are drawn from the first font that has them.
+    pub fonts: Vec<TextFont>,
+    pub scale: rusttype::Scale,
+    /// Widens the glyphs for a synthetic bold.
+    pub bold: bool,
+}
+
+/// Glyph positioned by the shaper, distances in pixels.
+#[derive(Debug, Copy, Clone)]
+pub struct ShapedGlyph {
+    /// Index of the style run of the glyph.
+    pub style: usize,
+    /// Index of the font in the fallback chain of the style run.
+    pub font: usize,
+    pub id: u16,
+    /// Byte offset in the text of the first char the glyph stands for.
+    pub cluster: usize,
+    pub advance: f32,
+    pub x_offset: f32,
+    pub y_offset: f32,
+}
+
+/// Laid out line with its glyphs in visual order, left to right.
+pub struct LayoutLine {
+    pub glyphs: Vec<ShapedGlyph>,
+    /// Advance width without the trailing whitespace.
+    pub width: f32,
+    /// Last line of its paragraph.
+    pub last: bool,
+    /// Largest ascent, descent and line gap of the styles on the line, the descent is
+    /// negative.
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+/// Shaped paragraph, glyphs of right to left runs are in visual order within their run.
+struct ShapedParagraph {
+    range: Range<usize>,
+    glyphs: Vec<ShapedGlyph>,
+    /// Glyphs of each shaped run, in logical order.
+    runs: Vec<Range<usize>>,
+    /// Advance from the paragraph start to every byte offset of its text.
+    carets: Vec<f32>,
+}
+
+impl ShapedParagraph {
+    fn caret_x(&self, index: usize) -> f32 {
+        self.carets[index - self.range.start]
+    }
+
+    fn width(&self, range: &Range<usize>) -> f32 {
+        self.caret_x(range.end) - self.caret_x(range.start)
+    }
+}
+
+/// Lays out `text` in lines no wider than `max_width`. Paragraphs are split at newlines, each
+/// resolved by the Unicode bidi algorithm and shaped run by run. `styles` cover the text in
+/// order, chars are drawn from the first font of their style that has them, or the first font
+/// when none has.
+pub fn layout_text(
+    text: &str,
+    styles: &[StyleRun],
+    max_width: Option<f32>,
+    max_lines: Option<usize>,
+) -> Vec<LayoutLine> {
+    let bidi_info = BidiInfo::new(text, None);
+    let mut lines = Vec::new();
+    for para in &bidi_info.paragraphs {
+        let end = para.range.start + text[para.range.clone()].trim_end_matches(['\n', '\r']).len();
+        let paragraph = shape_paragraph(text, &bidi_info, para.range.start..end, styles);
+        for (range, last) in break_lines(text, &paragraph, max_width) {
+            if max_lines.is_some_and(|max_lines| lines.len() >= max_lines) {
+                return lines;
+            }
+            lines.push(visual_line(&bidi_info, para, &paragraph, styles, range, last));
+        }
+    }
+    lines
+}
+
+fn shape_paragraph(
+    text: &str,
+    bidi_info: &BidiInfo,
+    range: Range<usize>,
+    styles: &[StyleRun],
+) -> ShapedParagraph {
+    let faces: Vec<Vec<rustybuzz::Face>> = styles
+        .iter()
+        .map(|style| style.fonts.iter().map(|font| font.face()).collect())
+        .collect();
+
+    // Runs of one embedding level, style and font are shaped in their direction.
+    let mut glyphs = Vec::new();
+    let mut runs = Vec::new();
+    let mut run: Option<(usize, usize, usize)> = None;
+    for (offset, c) in text[range.clone()].char_indices() {
+        let index = range.start + offset;
+        let style = style_at(styles, index);
+        let previous_font = run.filter(|&(_, run_style, _)| run_style == style).map(|(_, _, font)| font);
+        let font = font_for_char(c, &faces[style], previous_font);
+        if let Some((run_start, run_style, run_font)) = run {
+            if bidi_info.levels[index] != bidi_info.levels[run_start] || style != run_style || font != run_font {
+                let level = bidi_info.levels[run_start];
+                let first_glyph = glyphs.len();
+                shape_run(&faces[run_style][run_font], text, run_start..index, level.is_rtl(), styles, (run_style, run_font), &mut glyphs);
+                runs.push(first_glyph..glyphs.len());
+                run = None;
+            }
+        }
+        if run.is_none() {
+            run = Some((index, style, font));
+        }
+    }
+    if let Some((run_start, run_style, run_font)) = run {
+        let level = bidi_info.levels[run_start];
+        let first_glyph = glyphs.len();
+        shape_run(&faces[run_style][run_font], text, run_start..range.end, level.is_rtl(), styles, (run_style, run_font), &mut glyphs);
+        runs.push(first_glyph..glyphs.len());
+    }
+
+    let mut carets = vec![0.0; range.len() + 1];
+    for glyph in &glyphs {
+        carets[glyph.cluster - range.start + 1] += glyph.advance;
+    }
+    for index in 1..carets.len() {
+        carets[index] += carets[index - 1];
+    }
+    ShapedParagraph { range, glyphs, runs, carets }
+}
+
+/// Index of the style covering the byte at `index`.
+fn style_at(styles: &[StyleRun], index: usize) -> usize {
+    styles.iter().rposition(|style| style.range.start <= index).unwrap_or(0)
+}
+
+/// Font of the fallback chain to draw `c` with. Whitespace, marks and joiners stay in the
+/// font of the text before them, so clusters are not split between fonts.
+fn font_for_char(c: char, faces: &[rustybuzz::Face], previous: Option<usize>) -> usize {
+    if let Some(previous) = previous {
+        let class = unicode_bidi::bidi_class(c);
+        if class == BidiClass::BN || ((c.is_whitespace() || class == BidiClass::NSM) && faces[previous].glyph_index(c).is_some()) {
+            return previous;
+        }
+    }
+    faces.iter().position(|face| face.glyph_index(c).is_some()).unwrap_or(0)
+}
+
+fn shape_run(
+    face: &rustybuzz::Face,
+    text: &str,
+    range: Range<usize>,
+    rtl: bool,
+    styles: &[StyleRun],
+    (style, font): (usize, usize),
+    glyphs: &mut Vec<ShapedGlyph>,
+) {
+    let run_style = &styles[style];
+    let v_metrics = run_style.fonts[font].font().v_metrics_unscaled();
+    let units = v_metrics.ascent - v_metrics.descent;
+    let (scale_x, scale_y) = (run_style.scale.x / units, run_style.scale.y / units);
+    let bold_width = if run_style.bold { synthetic_bold_width(run_style.scale) } else { 0.0 };
+
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(&text[range.clone()]);
+    buffer.set_direction(if rtl {
+        rustybuzz::Direction::RightToLeft
+    } else {
+        rustybuzz::Direction::LeftToRight
+    });
+    buffer.guess_segment_properties();
+    let output = rustybuzz::shape(face, &[], buffer);
+    for (info, position) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+        let advance = position.x_advance as f32 * scale_x;
+        glyphs.push(ShapedGlyph {
+            style,
+            font,
+            id: info.glyph_id as u16,
+            cluster: range.start + info.cluster as usize,
+            advance: if advance > 0.0 { advance + bold_width } else { advance },
+            x_offset: position.x_offset as f32 * scale_x,
+            y_offset: position.y_offset as f32 * scale_y,
+        });
+    }
+}
+
+/// Breaks a paragraph into lines no wider than `max_width`, at the line break opportunities
+/// of the text, or anywhere within words longer than a line. Lines come without their
+/// trailing whitespace.
+fn break_lines(text: &str, paragraph: &ShapedParagraph, max_width: Option<f32>) -> Vec<(Range<usize>, bool)> {
+    let start = paragraph.range.start;
+    let trimmed = |range: Range<usize>| range.start..range.start + text[range].trim_end().len();
+    let max_width = match max_width {
+        Some(max_width) => max_width,
+        None => return vec![(trimmed(paragraph.range.clone()), true)],
+    };
+    let fits = |range: Range<usize>| paragraph.width(&trimmed(range)) <= max_width;
+    // Cluster boundaries, where a word may be broken.
+    let mut cluster_ends: Vec<usize> = paragraph
+        .glyphs
+        .iter()
+        .map(|glyph| glyph.cluster)
+        .filter(|&cluster| cluster > start)
+        .chain(Some(paragraph.range.end))
+        .collect();
+    cluster_ends.sort();
+    cluster_ends.dedup();
+
+    let mut lines = Vec::new();
+    let mut line_start = start;
+    let mut last_break = None;
+    for (offset, _) in linebreaks(&text[paragraph.range.clone()]) {
+        let index = start + offset;
+        if !fits(line_start..index) {
+            if let Some(last_break) = last_break.filter(|&last_break| last_break > line_start) {
+                lines.push((trimmed(line_start..last_break), false));
+                line_start = last_break;
+            }
+            while !fits(line_start..index) {
+                let end = cluster_ends
+                    .iter()
+                    .cloned()
+                    .filter(|&end| end > line_start && end <= index)
+                    .take_while(|&end| fits(line_start..end))
+                    .last()
+                    .or_else(|| cluster_ends.iter().cloned().find(|&end| end > line_start))
+                    .unwrap_or(index);
+                lines.push((trimmed(line_start..end), false));
+                line_start = end;
+            }
+        }
+        last_break = Some(index);
+    }
+    lines.push((trimmed(line_start..paragraph.range.end), true));
+    lines
+}
+
+/// Glyphs of a line reordered to visual order.
+fn visual_line(
+    bidi_info: &BidiInfo,
+    para: &ParagraphInfo,
+    paragraph: &ShapedParagraph,
+    styles: &[StyleRun],
+    range: Range<usize>,
+    last: bool,
+) -> LayoutLine {
+    let mut glyphs: Vec<ShapedGlyph> = Vec::new();
+    if !range.is_empty() {
+        let (_, runs) = bidi_info.visual_runs(para, range.clone());
+        for run in runs {
+            // Shaped runs split by style or font follow each other right to left in a right to
+            // left run, the glyphs within each are in visual order already.
+            let mut shaped_runs: Vec<&[ShapedGlyph]> = paragraph
+                .runs
+                .iter()
+                .map(|glyph_range| &paragraph.glyphs[glyph_range.clone()])
+                .collect();
+            if bidi_info.levels[run.start].is_rtl() {
+                shaped_runs.reverse();
+            }
+            for shaped_run in shaped_runs {
+                glyphs.extend(shaped_run.iter().filter(|glyph| run.contains(&glyph.cluster)));
+            }
+        }
+    }
+    // Lines share a baseline, sized by the largest style on them.
+    let mut line_styles: Vec<usize> = glyphs.iter().map(|glyph| glyph.style).collect();
+    if line_styles.is_empty() && !styles.is_empty() {
+        line_styles.push(style_at(styles, range.start));
+    }
+    let (mut ascent, mut descent, mut line_gap) = (0.0_f32, 0.0_f32, 0.0_f32);
+    for style in line_styles {
+        let v_metrics = styles[style].fonts[0].font().v_metrics(styles[style].scale);
+        ascent = ascent.max(v_metrics.ascent);
+        descent = descent.min(v_metrics.descent);
+        line_gap = line_gap.max(v_metrics.line_gap);
+    }
+    LayoutLine {
+        glyphs,
+        width: paragraph.width(&range),
+        last,
+        ascent,
+        descent,
+        line_gap,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style_run(range: Range<usize>, bold: bool) -> StyleRun {
+        StyleRun {
+            range,
+            fonts: vec![TextFont::default()],
+            scale: rusttype::Scale::uniform(16.0),
+            bold,
+        }
+    }
+
+    fn clusters(text: &str, styles: &[StyleRun]) -> Vec<usize> {
+        let lines = layout_text(text, styles, None, None);
+        lines[0].glyphs.iter().map(|glyph| glyph.cluster).collect()
+    }
+
+    #[test]
+    fn right_to_left_order_is_kept_across_styles() {
+        let text = "אבג דהו";
+        let expected = vec![11, 9, 7, 6, 4, 2, 0];
+        assert_eq!(clusters(text, &[style_run(0..text.len(), false)]), expected);
+        assert_eq!(clusters(text, &[style_run(0..7, false), style_run(7..text.len(), true)]), expected);
+    }
+}
@@ -1,18 +1,57 @@
 extern crate image;
+extern crate resvg;
+extern crate tiny_skia;
 
 use std::fs;
 use std::io;
+use std::rc::Rc;
+use std::time;
 use image::{GenericImage, DynamicImage, imageops};
+use resvg::usvg;
 
+use animation::{is_apng, Animation};
 use dimension::*;
+use image_filter::{apply_filters, ImageFilter};
 use screen_writer::{ScreenInfo};
-use sprite::{Sprite, render_to_canvas};
+use sprite::{Sprite, pixels_from_rgba, pixels_from_pixmap};
+use texture_cache::SharedTextureCache;
+
+/// How an image is sized into the node frame.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Fit {
+    /// Scales to fit inside the frame keeping the aspect ratio.
+    Contain,
+    /// Scales to fill the frame keeping the aspect ratio, cropping the overflow.
+    Cover,
+    /// Stretches to the frame size.
+    Fill,
+    /// Keeps the original pixel size.
+    None,
+    /// Repeats the image at its original pixel size.
+    Tile,
+    /// Like `None`, or `Contain` when the image is larger than the frame.
+    ScaleDown,
+}
+
+enum Texture {
+    /// Raster image, shared with other sprites through the texture cache.
+    Image(Rc<DynamicImage>),
+    /// Vector image, rasterized at the laid-out size on every draw.
+    Svg(Box<usvg::Tree>),
+    /// Animated GIF or APNG, all frames scaled once to the laid-out size.
+    Animation(Box<Animation>),
+}
 
 pub struct TextureSprite {
     pub gravity : Gravity,
     pub filter : imageops::FilterType,
-    texture : Option<DynamicImage>,
-    raw_pixels : Vec<u8>,
+    pub fit : Fit,
+    /// Color filters applied in order after scaling.
+    pub filters : Vec<ImageFilter>,
+    texture : Option<Texture>,
+    texture_filename : String,
+    texture_cache : Option<SharedTextureCache>,
+    raw_pixels : Rc<Vec<u32>>,
     frame : Rect,
 }
 
@@ -21,24 +60,82 @@ impl TextureSprite {
     pub fn new() -> TextureSprite {
 
         TextureSprite {
-            raw_pixels : Vec::new(),
+            raw_pixels : Rc::new(Vec::new()),
             gravity : GRAVITY_CENTER,
+            fit : Fit::Contain,
+            filters : Vec::new(),
             texture : None,
+            texture_filename : String::new(),
+            texture_cache : None,
             filter : imageops::Triangle,
             frame : RECT_ZERO,
         }
     }
 
-    fn load_image(filename: &str) -> Result<DynamicImage, &str> {
-        match fs::File::open(filename) {
-            Ok(file) => {
-                let reader = io::BufReader::new(file);
-                if filename.to_lowercase().ends_with(".png") {
-                    Ok(image::load(reader, image::PNG).unwrap())
-                } else if filename.to_lowercase().ends_with(".jpg") {
-                    Ok(image::load(reader, image::JPEG).unwrap())
+    fn load_image(filename: &str, texture_cache: Option<&SharedTextureCache>) -> Result<Texture, &'static str> {
+        let lowercase_filename = filename.to_lowercase();
+        if lowercase_filename.ends_with(".svg") {
+            return TextureSprite::load_svg(filename);
+        }
+        let is_gif = lowercase_filename.ends_with(".gif");
+        let is_png = lowercase_filename.ends_with(".png") || lowercase_filename.ends_with(".apng");
+        if is_gif || (is_png && is_apng(filename)) {
+            let load = || {
+                if is_gif {
+                    Animation::load_gif(filename)
                 } else {
-                    Ok(image::load(reader, image::PNG).unwrap())
+                    Animation::load_apng(filename)?.ok_or_else(|| format!("No animation control in {}", filename))
+                }
+            };
+            let animation = match texture_cache {
+                Some(texture_cache) => texture_cache.borrow_mut().animation(filename, load),
+                None => load(),
+            };
+            return match animation {
+                Ok(animation) => Ok(Texture::Animation(Box::new(animation))),
+                Err(e) => {
+                    println!("{}", e);
+                    Err("Load animation failed")
+                }
+            };
+        }
+        let image = match texture_cache {
+            Some(texture_cache) => texture_cache.borrow_mut().image(filename, || TextureSprite::load_raster(filename)),
+            None => TextureSprite::load_raster(filename).map(Rc::new),
+        };
+        match image {
+            Ok(image) => Ok(Texture::Image(image)),
+            Err(e) => {
+                println!("{}", e);
+                Err("Open image failed")
+            }
+        }
+    }
+
+    fn load_raster(filename: &str) -> Result<DynamicImage, String> {
+        let file = fs::File::open(filename).map_err(|e| format!("{} {}", e, filename))?;
+        let reader = io::BufReader::new(file);
+        let format = if filename.to_lowercase().ends_with(".jpg") {
+            image::JPEG
+        } else {
+            image::PNG
+        };
+        image::load(reader, format).map_err(|e| format!("{} {}", e, filename))
+    }
+
+    fn load_svg(filename: &str) -> Result<Texture, &'static str> {
+        match fs::read(filename) {
+            Ok(data) => {
+                let options = usvg::Options {
+                    resources_dir : ::std::path::Path::new(filename).parent().map(|path| path.to_path_buf()),
+                    ..usvg::Options::default()
+                };
+                match usvg::Tree::from_data(&data, &options) {
+                    Ok(tree) => Ok(Texture::Svg(Box::new(tree))),
+                    Err(e) => {
+                        println!("{} {}", e, filename);
+                        Err("Parse svg failed")
+                    }
                 }
             },
             Err(e) => {
@@ -50,66 +147,236 @@ impl TextureSprite {
 
     pub fn new_for_texture(filename: &str) -> Result<TextureSprite, &str> {
 
-        match TextureSprite::load_image(filename) {
+        match TextureSprite::load_image(filename, None) {
             Ok(load_result) => {
                 Ok(TextureSprite {
-                    raw_pixels : load_result.raw_pixels(),
+                    raw_pixels : Rc::new(Vec::new()),
                     filter : imageops::Triangle,
                     gravity : GRAVITY_CENTER,
+                    fit : Fit::Contain,
+                    filters : Vec::new(),
                     texture : Some(load_result),
+                    texture_filename : filename.to_string(),
+                    texture_cache : None,
                     frame : RECT_ZERO,
                 })
             },
-            Err(e) => {
+            Err(_) => {
                 Err("Load image failed")
             }
         }
     }
 
+    /// Shares decoded images and resized variants with other sprites. Set it before the texture
+    /// filename so loading goes through the cache too.
+    pub fn set_texture_cache(&mut self, texture_cache: &SharedTextureCache) {
+        self.texture_cache = Some(texture_cache.clone());
+    }
+
     pub fn set_texture_filename(&mut self, filename: &str) {
-        match TextureSprite::load_image(filename) {
+        match TextureSprite::load_image(filename, self.texture_cache.as_ref()) {
             Ok(load_result) => {
                 self.texture = Some(load_result);
+                self.texture_filename = filename.to_string();
             },
             Err(e) => {
                 println!("{}", e);
             }
         }
     }
+
+    pub fn is_animated(&self) -> bool {
+        matches!(self.texture, Some(Texture::Animation(_)))
+    }
+
+    /// Starts or resumes an animated image, a finished animation starts over.
+    pub fn play(&mut self) {
+        if let Some(Texture::Animation(ref mut animation)) = self.texture {
+            animation.play();
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(Texture::Animation(ref mut animation)) = self.texture {
+            animation.pause();
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        match self.texture {
+            Some(Texture::Animation(ref animation)) => animation.is_playing(),
+            _ => false,
+        }
+    }
+
+    /// Number of plays of an animated image, 0 loops forever. Defaults to the file's setting.
+    pub fn set_loop_count(&mut self, loop_count: u32) {
+        if let Some(Texture::Animation(ref mut animation)) = self.texture {
+            animation.loop_count = loop_count;
+        }
+    }
+
+    fn rasterize_svg(tree: &usvg::Tree, width: i32, height: i32) -> Option<Vec<u32>> {
+        match tiny_skia::Pixmap::new(width.max(1) as u32, height.max(1) as u32) {
+            Some(mut pixmap) => {
+                let tree_size = tree.size();
+                let transform = tiny_skia::Transform::from_scale(
+                    width as f32 / tree_size.width(),
+                    height as f32 / tree_size.height(),
+                );
+                resvg::render(tree, transform, &mut pixmap.as_mut());
+                Some(pixels_from_pixmap(&pixmap))
+            },
+            None => None,
+        }
+    }
 }
 
 impl<'a> Sprite<'a> for TextureSprite {
 
     fn draw(&mut self, outer_rect:&Rect, _screen_info:&ScreenInfo) {
-        if let Some(ref image) = self.texture {
+        if let Some(ref mut texture) = self.texture {
 
-            let frame_aspect = outer_rect.size.width as f32 / outer_rect.size.height as f32;
-            let image_aspect = image.width() as f32 / image.height() as f32;
+            let (texture_width, texture_height) = match *texture {
+                Texture::Image(ref image) => (image.width() as f32, image.height() as f32),
+                Texture::Svg(ref tree) => (tree.size().width(), tree.size().height()),
+                Texture::Animation(ref animation) => (animation.width() as f32, animation.height() as f32),
+            };
+            let size = fitted_size(self.fit, texture_width, texture_height, &outer_rect.size);
+            let (width, height) = (size.width, size.height);
 
-            let mut width: i32;
-            let mut height: i32;
+            // None for animations, their scaled frames are kept by the animation.
+            let scaled_pixels = match *texture {
+                Texture::Image(ref image) => {
+                    if let Some(ref texture_cache) = self.texture_cache {
+                        Some(texture_cache.borrow_mut().resized(
+                            &self.texture_filename, image, width as u32, height as u32, self.filter, &self.filters))
+                    } else {
+                        let new_image = image.resize_exact(width as u32, height as u32, self.filter);
+                        let mut pixels = pixels_from_rgba(&new_image.to_rgba().into_raw());
+                        apply_filters(&mut pixels, &size, &self.filters);
+                        Some(Rc::new(pixels))
+                    }
+                },
+                Texture::Svg(ref tree) => match TextureSprite::rasterize_svg(tree, width, height) {
+                    Some(mut pixels) => {
+                        apply_filters(&mut pixels, &size, &self.filters);
+                        Some(Rc::new(pixels))
+                    },
+                    None => {
+                        println!("Can't rasterize {} at {}x{}", self.texture_filename, width, height);
+                        self.raw_pixels = Rc::new(Vec::new());
+                        self.frame = RECT_ZERO;
+                        return;
+                    },
+                },
+                Texture::Animation(ref mut animation) => {
+                    animation.scale_to(width, height, self.filter, &self.filters);
+                    None
+                },
+            };
 
-            if frame_aspect < image_aspect {
-                width = outer_rect.size.width;
-                height = (width as f32 / image_aspect) as i32;
-            } else {
-                height = outer_rect.size.height;
-                width = (height as f32 * image_aspect) as i32;
+            let (crop, frame) = visible_rect(&size, &outer_rect.size, &self.gravity);
+            let needs_copy = self.fit == Fit::Tile || crop.size.width != width || crop.size.height != height;
+            self.raw_pixels = match scaled_pixels {
+                Some(scaled_pixels) if !needs_copy => scaled_pixels,
+                // Animation frames that need no copy are served from the animation itself, see `pixels`.
+                None if !needs_copy => Rc::new(Vec::new()),
+                scaled_pixels => {
+                    let pixels: &[u32] = match (&scaled_pixels, &*texture) {
+                        (Some(scaled_pixels), _) => scaled_pixels,
+                        (None, Texture::Animation(animation)) => animation.scaled_pixels(),
+                        (None, _) => &[],
+                    };
+                    if self.fit == Fit::Tile {
+                        Rc::new(tile_pixels(pixels, &size, &outer_rect.size))
+                    } else {
+                        Rc::new(crop_pixels(pixels, width, &crop))
+                    }
+                },
+            };
+            if self.fit == Fit::Tile {
+                self.frame = Rect {pos : POS_ZERO, size : outer_rect.size};
+                return;
             }
+            self.frame = frame;
+        }
+    }
 
-            let new_image = image.resize(width as u32, height as u32, self.filter);
-            self.raw_pixels = new_image.raw_pixels();
-            width = new_image.width() as i32;
-            height = new_image.height() as i32;
+    fn pixels(&self) -> (&[u32], &Rect) {
+        if let Some(Texture::Animation(ref animation)) = self.texture {
+            if self.raw_pixels.is_empty() {
+                return (animation.scaled_pixels(), &self.frame);
+            }
+        }
+        (&self.raw_pixels, &self.frame)
+    }
 
-            let mut frame = Rect {pos : POS_ZERO, size : Size {width : width, height : height}};
-            frame.pos.x = ((outer_rect.size.width as f32 * self.gravity.x) - (frame.size.width as f32 * self.gravity.x)) as i32;
-            frame.pos.y = ((outer_rect.size.height as f32 * self.gravity.y) - (frame.size.height as f32 * self.gravity.y)) as i32;
-            self.frame = frame;
+    fn update(&mut self, clock: time::Duration) -> bool {
+        match self.texture {
+            Some(Texture::Animation(ref mut animation)) => animation.update(clock),
+            _ => false,
         }
     }
+}
+
+/// Scaled image size for `fit`, at least one pixel.
+fn fitted_size(fit: Fit, texture_width: f32, texture_height: f32, outer_size: &Size) -> Size {
+    let width_scale = outer_size.width as f32 / texture_width;
+    let height_scale = outer_size.height as f32 / texture_height;
+    let scale = match fit {
+        Fit::Contain => width_scale.min(height_scale),
+        Fit::Cover => width_scale.max(height_scale),
+        Fit::None | Fit::Tile => 1.0,
+        Fit::ScaleDown => width_scale.min(height_scale).min(1.0),
+        Fit::Fill => {
+            return Size {width : outer_size.width.max(1), height : outer_size.height.max(1)};
+        },
+    };
+    Size {
+        width : ((texture_width * scale).round() as i32).max(1),
+        height : ((texture_height * scale).round() as i32).max(1),
+    }
+}
+
+/// Part of a `size` image shown in the outer frame when aligned by `gravity`, and where it is shown.
+fn visible_rect(size: &Size, outer_size: &Size, gravity: &Gravity) -> (Rect, Rect) {
+    let visible_size = Size {
+        width : size.width.min(outer_size.width).max(1),
+        height : size.height.min(outer_size.height).max(1),
+    };
+    let crop = Rect {
+        pos : Pos {
+            x : ((size.width - visible_size.width) as f32 * gravity.x) as i32,
+            y : ((size.height - visible_size.height) as f32 * gravity.y) as i32,
+        },
+        size : visible_size,
+    };
+    let frame = Rect {
+        pos : Pos {
+            x : ((outer_size.width - visible_size.width) as f32 * gravity.x) as i32,
+            y : ((outer_size.height - visible_size.height) as f32 * gravity.y) as i32,
+        },
+        size : visible_size,
+    };
+    (crop, frame)
+}
+
+fn crop_pixels(pixels: &[u32], width: i32, crop: &Rect) -> Vec<u32> {
+    let mut cropped = Vec::with_capacity((crop.size.width * crop.size.height) as usize);
+    for y in crop.pos.y..crop.pos.y + crop.size.height {
+        let row_start = (y * width + crop.pos.x) as usize;
+        cropped.extend_from_slice(&pixels[row_start..row_start + crop.size.width as usize]);
+    }
+    cropped
+}
 
-    fn render(&mut self, parent_node_frame:&Rect, node_frame:&Rect, clip_to_bounds:bool, screen_info:&ScreenInfo, canvas_ptr:*mut u32) {
-        render_to_canvas(self.raw_pixels.as_slice().as_ptr() as *mut u32, parent_node_frame, node_frame, &self.frame, clip_to_bounds, screen_info, canvas_ptr);
+fn tile_pixels(pixels: &[u32], size: &Size, outer_size: &Size) -> Vec<u32> {
+    let mut tiled = Vec::with_capacity((outer_size.width * outer_size.height) as usize);
+    for y in 0..outer_size.height {
+        let row_start = ((y % size.height) * size.width) as usize;
+        let row = &pixels[row_start..row_start + size.width as usize];
+        tiled.extend(row.iter().cycle().take(outer_size.width as usize));
     }
+    tiled
 }
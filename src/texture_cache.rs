@@ -0,0 +1,187 @@
+extern crate image;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use image::{imageops, DynamicImage, GenericImage};
+
+use animation::{Animation, AnimationFrame};
+use dimension::*;
+use image_filter::{apply_filters, filters_key, ImageFilter};
+use sprite::pixels_from_rgba;
+
+pub const DEFAULT_TEXTURE_CACHE_BUDGET: usize = 64 * 1024 * 1024;
+
+pub type SharedTextureCache = Rc<RefCell<TextureCache>>;
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+enum CacheKey {
+    Image(String),
+    Resized(String, u32, u32, u8, String),
+}
+
+#[derive(Clone)]
+enum CacheValue {
+    Image(Rc<DynamicImage>),
+    Animation(Rc<Vec<AnimationFrame>>, u32),
+    Pixels(Rc<Vec<u32>>),
+}
+
+struct CacheEntry {
+    value: CacheValue,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// Scene wide cache of decoded images and their resized variants, keyed by file path.
+/// Least recently used entries are evicted once the memory budget is exceeded. Sprites keep
+/// the values they hold alive after eviction.
+pub struct TextureCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    budget: usize,
+    used: usize,
+    tick: u64,
+}
+
+impl TextureCache {
+    pub fn new(budget: usize) -> TextureCache {
+        TextureCache {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+            tick: 0,
+        }
+    }
+
+    pub fn new_shared(budget: usize) -> SharedTextureCache {
+        Rc::new(RefCell::new(TextureCache::new(budget)))
+    }
+
+    /// Decoded image for `path`, loaded with `load` on a miss.
+    pub fn image<F>(&mut self, path: &str, load: F) -> Result<Rc<DynamicImage>, String>
+    where
+        F: FnOnce() -> Result<DynamicImage, String>,
+    {
+        let key = CacheKey::Image(path.to_string());
+        if let Some(CacheValue::Image(image)) = self.touch(&key) {
+            return Ok(image);
+        }
+        let image = Rc::new(load()?);
+        let bytes = image_bytes(&image);
+        self.insert(key, CacheValue::Image(image.clone()), bytes);
+        Ok(image)
+    }
+
+    /// Animation for `path`, loaded with `load` on a miss. Animations of a path share their
+    /// decoded frames, each keeps its own playback state.
+    pub fn animation<F>(&mut self, path: &str, load: F) -> Result<Animation, String>
+    where
+        F: FnOnce() -> Result<Animation, String>,
+    {
+        let key = CacheKey::Image(path.to_string());
+        if let Some(CacheValue::Animation(frames, loop_count)) = self.touch(&key) {
+            return Ok(Animation::new(frames, loop_count));
+        }
+        let animation = load()?;
+        let frames = animation.frames();
+        let bytes = frames.iter().map(|frame| frame.image.len()).sum();
+        self.insert(key, CacheValue::Animation(frames, animation.loop_count), bytes);
+        Ok(animation)
+    }
+
+    /// Pixels of the image at `path` resized to `width` x `height` with `filters` applied,
+    /// memoized by size, resampling filter and color filters.
+    pub fn resized(
+        &mut self,
+        path: &str,
+        image: &DynamicImage,
+        width: u32,
+        height: u32,
+        filter: imageops::FilterType,
+        filters: &[ImageFilter],
+    ) -> Rc<Vec<u32>> {
+        let key = CacheKey::Resized(path.to_string(), width, height, filter as u8, filters_key(filters));
+        if let Some(CacheValue::Pixels(pixels)) = self.touch(&key) {
+            return pixels;
+        }
+        let mut pixels = if image.width() == width && image.height() == height {
+            pixels_from_rgba(&image.to_rgba().into_raw())
+        } else {
+            pixels_from_rgba(&image.resize_exact(width, height, filter).to_rgba().into_raw())
+        };
+        let size = Size { width: width as i32, height: height as i32 };
+        apply_filters(&mut pixels, &size, filters);
+        let pixels = Rc::new(pixels);
+        let bytes = pixels.len() * 4;
+        self.insert(key, CacheValue::Pixels(pixels.clone()), bytes);
+        pixels
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    /// Bytes currently held by the cache.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used = 0;
+    }
+
+    fn touch(&mut self, key: &CacheKey) -> Option<CacheValue> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(key).map(|entry| {
+            entry.last_used = tick;
+            entry.value.clone()
+        })
+    }
+
+    fn insert(&mut self, key: CacheKey, value: CacheValue, bytes: usize) {
+        self.tick += 1;
+        if let Some(entry) = self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                bytes,
+                last_used: self.tick,
+            },
+        ) {
+            self.used -= entry.bytes;
+        }
+        self.used += bytes;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.budget && !self.entries.is_empty() {
+            let oldest_key = self
+                .entries
+                .iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = oldest_key {
+                if let Some(entry) = self.entries.remove(&key) {
+                    self.used -= entry.bytes;
+                }
+            }
+        }
+    }
+}
+
+/// Bytes the decoded pixels of `image` take, without copying them out like `raw_pixels`.
+fn image_bytes(image: &DynamicImage) -> usize {
+    let bits_per_pixel = match image.color() {
+        image::Gray(bits) => bits as usize,
+        image::GrayA(bits) => 2 * bits as usize,
+        image::RGB(bits) | image::Palette(bits) => 3 * bits as usize,
+        image::RGBA(bits) => 4 * bits as usize,
+    };
+    let (width, height) = image.dimensions();
+    width as usize * height as usize * bits_per_pixel / 8
+}
@@ -0,0 +1,132 @@
+use std::f32;
+
+use dimension::*;
+
+/// 2D affine transform mapping `(x, y)` to `(a * x + c * y + tx, b * x + d * y + ty)`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+pub const TRANSFORM_IDENTITY: Transform = Transform {
+    a: 1.0,
+    b: 0.0,
+    c: 0.0,
+    d: 1.0,
+    tx: 0.0,
+    ty: 0.0,
+};
+
+impl Transform {
+    pub fn translation(x: f32, y: f32) -> Transform {
+        Transform {
+            tx: x,
+            ty: y,
+            ..TRANSFORM_IDENTITY
+        }
+    }
+
+    pub fn rotation(degrees: f32) -> Transform {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Transform {
+            a: cos,
+            b: sin,
+            c: -sin,
+            d: cos,
+            ..TRANSFORM_IDENTITY
+        }
+    }
+
+    pub fn scale(scale: &Scale) -> Transform {
+        Transform {
+            a: scale.x,
+            d: scale.y,
+            ..TRANSFORM_IDENTITY
+        }
+    }
+
+    pub fn skew(skew: &Skew) -> Transform {
+        Transform {
+            b: skew.y.to_radians().tan(),
+            c: skew.x.to_radians().tan(),
+            ..TRANSFORM_IDENTITY
+        }
+    }
+
+    /// Returns the transform that applies `other` first and then `self`.
+    pub fn multiply(&self, other: &Transform) -> Transform {
+        Transform {
+            a: self.a * other.a + self.c * other.b,
+            b: self.b * other.a + self.d * other.b,
+            c: self.a * other.c + self.c * other.d,
+            d: self.b * other.c + self.d * other.d,
+            tx: self.a * other.tx + self.c * other.ty + self.tx,
+            ty: self.b * other.tx + self.d * other.ty + self.ty,
+        }
+    }
+
+    pub fn invert(&self) -> Option<Transform> {
+        let det = self.a * self.d - self.b * self.c;
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let a = self.d / det;
+        let b = -self.b / det;
+        let c = -self.c / det;
+        let d = self.a / det;
+        Some(Transform {
+            a,
+            b,
+            c,
+            d,
+            tx: -(a * self.tx + c * self.ty),
+            ty: -(b * self.tx + d * self.ty),
+        })
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.a * x + self.c * y + self.tx,
+            self.b * x + self.d * y + self.ty,
+        )
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == TRANSFORM_IDENTITY
+    }
+
+    /// Bounding box of `rect` after the transform is applied.
+    pub fn bounding_rect(&self, rect: &Rect) -> Rect {
+        let left = rect.pos.x as f32;
+        let top = rect.pos.y as f32;
+        let right = (rect.pos.x + rect.size.width) as f32;
+        let bottom = (rect.pos.y + rect.size.height) as f32;
+
+        let mut min_x = f32::MAX;
+        let mut min_y = f32::MAX;
+        let mut max_x = f32::MIN;
+        let mut max_y = f32::MIN;
+        for &(x, y) in &[(left, top), (right, top), (left, bottom), (right, bottom)] {
+            let (x, y) = self.apply(x, y);
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        Rect {
+            pos: Pos {
+                x: min_x.floor() as i32,
+                y: min_y.floor() as i32,
+            },
+            size: Size {
+                width: (max_x.ceil() - min_x.floor()) as i32,
+                height: (max_y.ceil() - min_y.floor()) as i32,
+            },
+        }
+    }
+}